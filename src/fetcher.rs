@@ -1,15 +1,42 @@
-use crate::state::TodoGroup;
+use crate::{
+    rate_limit::{EndpointClass, RateLimiters},
+    state::TodoGroup,
+    EndpointConfig, RateLimitConfig, ReplayFilters,
+};
 use anyhow::Context;
+use futures::{Stream, StreamExt};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
+use std::time::Duration;
 use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
 
 const BALLCHASING_API_ROOT: &str = "https://ballchasing.com/api";
 
+/// The maximum number of items that the ballchasing api will return in a single page.
+pub const MAX_PAGE_SIZE: u32 = 200;
+
+/// The default number of replays to fetch concurrently.
+const DEFAULT_REPLAY_CONCURRENCY: u32 = 4;
+/// The maximum number of replays to fetch concurrently, which matches the endpoint config
+/// schema.
+const MAX_REPLAY_CONCURRENCY: u32 = 32;
+
+/// Requests that take longer than this will be failed and retried.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+/// The delay before the first retry of a failed request, which doubles with each attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The maximum delay between two attempts of the same request.
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+/// The maximum total time spent on a single request, including all of its retries.
+const MAX_RETRY_DURATION: Duration = Duration::from_secs(30 * 60);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GroupSummary {
     pub id: String,
     pub name: String,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub created: Option<OffsetDateTime>,
     pub direct_replays: Option<i64>,
     pub indirect_replays: Option<i64>,
 }
@@ -17,6 +44,16 @@ pub struct GroupSummary {
 #[derive(Serialize, Deserialize, Debug)]
 struct GroupListing {
     list: Vec<GroupSummary>,
+    /// The full url of the next page of results, which is absent on the last page.
+    next: Option<String>,
+}
+
+/// A single page of groups that still need to be processed.
+#[derive(Debug)]
+pub struct GroupPage {
+    pub groups: Vec<TodoGroup>,
+    /// The url of the next page, which can be passed as the `cursor` to fetch it.
+    pub next: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
@@ -27,6 +64,16 @@ pub enum Visibility {
     Unlisted,
 }
 
+/// Whether ballchasing has finished processing a replay. Replays are `pending` for a
+/// little while after they're uploaded, and won't have any stats until they're `ok`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayStatus {
+    Ok,
+    Pending,
+    Failed,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Uploader {
     pub steam_id: String,
@@ -44,125 +91,638 @@ pub struct ReplaySummary {
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ReplayListing {
+    /// The total number of replays matching the query, across all pages.
+    count: Option<i64>,
     list: Vec<ReplaySummary>,
+    /// The full url of the next page of results, which is absent on the last page.
+    next: Option<String>,
+}
+
+/// A single page of replays.
+#[derive(Debug)]
+pub struct ReplayPage {
+    pub replays: Vec<ReplaySummary>,
+    /// The url of the next page, which can be passed as the `cursor` to fetch it.
+    pub next: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PingResponse {
     pub steam_id: String,
+    /// The patreon tier of the account, which determines its rate limits.
+    #[serde(rename = "type", default)]
+    pub tier: PatreonTier,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PatreonTier {
+    #[default]
+    Regular,
+    Gold,
+    Diamond,
+    Champion,
+    Gc,
+    /// Any tier that's unknown to us, which gets the same limits as `Regular`.
+    #[serde(other)]
+    Other,
 }
 
 pub struct Fetcher {
     client: reqwest::Client,
     auth_token: String,
-    rate_limiter: governor::DefaultDirectRateLimiter,
+    rate_limiters: RateLimiters,
+    rate_limit_overrides: RateLimitConfig,
+    page_size: u32,
+    /// The maximum number of replays to fetch concurrently in `fetch_replays`.
+    replay_concurrency: usize,
+    /// The root url of the api, without a trailing slash.
+    api_root: String,
+    /// Cancelled when the connector is shutting down, which interrupts any request that's
+    /// waiting on a rate limit or a retry. Requests that have already been sent are
+    /// allowed to finish, since their responses can still be emitted.
+    shutdown: CancellationToken,
 }
 
-fn api_url(rel_path: &str) -> String {
-    format!("{BALLCHASING_API_ROOT}/{rel_path}")
+/// Formats a timestamp for use in a query parameter.
+pub fn format_rfc3339(t: OffsetDateTime) -> String {
+    t.format(&time::format_description::well_known::Rfc3339)
+        .expect("timestamps must be formattable as rfc3339")
+}
+
+/// Validates that the given base url is an absolute http(s) url, and returns it with any
+/// trailing slashes removed so that relative paths can be appended to it.
+fn parse_api_root(base_url: &str) -> anyhow::Result<String> {
+    let url = reqwest::Url::parse(base_url)
+        .with_context(|| format!("invalid api base url '{base_url}'"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!(
+            "invalid api base url '{base_url}': scheme must be http or https, got '{}'",
+            url.scheme()
+        );
+    }
+    if url.cannot_be_a_base() || url.host_str().is_none() {
+        anyhow::bail!("invalid api base url '{base_url}': url must have a host");
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        anyhow::bail!("invalid api base url '{base_url}': url must not have a query or fragment");
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
 }
 
 impl Fetcher {
-    pub fn new(auth_token: String) -> Self {
-        Self {
-            auth_token,
-            client: reqwest::Client::new(),
-            rate_limiter: governor::RateLimiter::direct(
-                governor::Quota::with_period(std::time::Duration::from_millis(500)).unwrap(),
-            ),
-        }
+    pub fn new(config: &EndpointConfig) -> anyhow::Result<Self> {
+        let api_root = parse_api_root(
+            config
+                .api_base_url
+                .as_deref()
+                .unwrap_or(BALLCHASING_API_ROOT),
+        )?;
+        Ok(Self {
+            api_root,
+            auth_token: config.auth_token.clone(),
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .context("building http client")?,
+            rate_limiters: RateLimiters::new(PatreonTier::Regular, &config.rate_limits),
+            rate_limit_overrides: config.rate_limits,
+            page_size: config
+                .page_size
+                .unwrap_or(MAX_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE),
+            replay_concurrency: config
+                .replay_concurrency
+                .unwrap_or(DEFAULT_REPLAY_CONCURRENCY)
+                .clamp(1, MAX_REPLAY_CONCURRENCY) as usize,
+            shutdown: CancellationToken::new(),
+        })
     }
-    /// GETs the api root to test authentication and return the `steam_id` of the caller.
-    pub async fn ping_server(&self) -> anyhow::Result<PingResponse> {
-        self.fetch_json(api_url(""), Option::<&'_ [(&str, &str)]>::None)
-            .await
+
+    /// Makes every subsequent request fail with `FetchError::Cancelled` once `shutdown` is
+    /// cancelled, instead of waiting for the rate limits or retrying.
+    pub fn set_shutdown(&mut self, shutdown: CancellationToken) {
+        self.shutdown = shutdown;
+    }
+
+    /// Replaces the rate limits, which initially assume a `Regular` account, with those of
+    /// the given tier. Any limits from the endpoint config still take precedence.
+    pub fn set_account_tier(&mut self, tier: PatreonTier) {
+        tracing::info!(?tier, "applying rate limits for account");
+        self.rate_limiters = RateLimiters::new(tier, &self.rate_limit_overrides);
+    }
+
+    fn api_url(&self, rel_path: &str) -> String {
+        format!("{}/{rel_path}", self.api_root)
     }
 
-    pub async fn fetch_replay(&self, replay_id: &str) -> anyhow::Result<Value> {
+    /// GETs the api root to test authentication and return the `steam_id` and tier of the caller.
+    pub async fn ping_server(&self) -> Result<PingResponse, FetchError> {
         self.fetch_json(
-            api_url(&format!("replays/{replay_id}")),
+            EndpointClass::Ping,
+            self.api_url(""),
             Option::<&'_ [(&str, &str)]>::None,
         )
         .await
-        .context("fetching replay")
     }
 
-    pub async fn fetch_replay_ids(&self, parent_id: &str) -> anyhow::Result<Vec<ReplaySummary>> {
-        let list: ReplayListing = self
-            .fetch_json(api_url("replays"), Some(&[("group", parent_id)]))
-            .await
-            .context("listing replays")?;
-        Ok(list.list)
+    pub async fn fetch_replay(&self, replay_id: &str) -> Result<Value, FetchError> {
+        self.fetch_json(
+            EndpointClass::GetReplay,
+            self.api_url(&format!("replays/{replay_id}")),
+            Option::<&'_ [(&str, &str)]>::None,
+        )
+        .await
     }
-    pub async fn fetch_child_groups(&self, parent_id: &str) -> anyhow::Result<Vec<TodoGroup>> {
-        let list: GroupListing = self
-            .fetch_json(api_url("groups"), Some(&[("group", parent_id)]))
-            .await?;
 
-        let groups = list
-            .list
-            .into_iter()
-            .map(TodoGroup::from)
-            .filter(|tg| !tg.is_done())
-            .collect();
-        Ok(groups)
+    /// Fetches each of the given replays, with up to `replay_concurrency` requests in flight
+    /// at once. The results are yielded in the same order as the input, regardless of the
+    /// order in which the requests complete.
+    pub fn fetch_replays<'a, I>(
+        &'a self,
+        replays: I,
+    ) -> impl Stream<Item = (I::Item, Result<Value, FetchError>)> + 'a
+    where
+        I: IntoIterator + 'a,
+        I::Item: AsRef<str> + 'a,
+    {
+        futures::stream::iter(replays)
+            .map(move |replay_id| async move {
+                let result = self.fetch_replay(replay_id.as_ref()).await;
+                (replay_id, result)
+            })
+            .buffered(self.replay_concurrency)
     }
 
-    pub async fn fetch_creator_groups(&self, creator_id: &str) -> anyhow::Result<Vec<TodoGroup>> {
-        let list: GroupListing = self
-            .fetch_json(api_url("groups"), Some(&[("creator", creator_id)]))
+    /// Lists all of the replays that are direct children of the given group, following
+    /// the `next` links until every page has been fetched.
+    pub async fn fetch_replay_ids(
+        &self,
+        parent_id: &str,
+        filters: &ReplayFilters,
+    ) -> Result<Vec<ReplaySummary>, FetchError> {
+        let mut query = vec![
+            ("group", parent_id.to_string()),
+            ("count", self.page_size.to_string()),
+        ];
+        query.extend(filters.query_params());
+        let mut listing: ReplayListing = self
+            .fetch_json(
+                EndpointClass::ListReplays,
+                self.api_url("replays"),
+                Some(query.as_slice()),
+            )
             .await?;
 
+        let mut replays = Vec::new();
+        loop {
+            replays.append(&mut listing.list);
+            let Some(next_url) = listing.next.take() else {
+                break;
+            };
+            tracing::debug!(%parent_id, fetched = replays.len(), total = ?listing.count, "fetching next page of replays");
+            listing = self
+                .fetch_json(
+                    EndpointClass::ListReplays,
+                    next_url,
+                    Option::<&'_ [(&str, &str)]>::None,
+                )
+                .await?;
+        }
+        Ok(replays)
+    }
+    /// Fetches the summary of a single group.
+    pub async fn fetch_group(&self, group_id: &str) -> Result<GroupSummary, FetchError> {
+        let details = self.fetch_group_details(group_id).await?;
+        serde_json::from_value(Value::Object(details)).map_err(FetchError::Decode)
+    }
+
+    /// Fetches all of the details of a single group, including its settings and the
+    /// cumulative stats of its players and teams.
+    pub async fn fetch_group_details(
+        &self,
+        group_id: &str,
+    ) -> Result<serde_json::Map<String, Value>, FetchError> {
+        self.fetch_json(
+            EndpointClass::Groups,
+            self.api_url(&format!("groups/{group_id}")),
+            Option::<&'_ [(&str, &str)]>::None,
+        )
+        .await
+    }
+
+    /// Fetches a single page of the replays uploaded by `uploader_id` that match the
+    /// `filters`, limited to those that were uploaded after `created_after` if it's given.
+    /// Paginates the same way as `fetch_group_page`.
+    pub async fn fetch_uploaded_replays(
+        &self,
+        uploader_id: &str,
+        created_after: Option<OffsetDateTime>,
+        filters: &ReplayFilters,
+        cursor: Option<&str>,
+    ) -> Result<ReplayPage, FetchError> {
+        let listing: ReplayListing = if let Some(next_url) = cursor {
+            self.fetch_json(
+                EndpointClass::ListReplays,
+                next_url.to_string(),
+                Option::<&'_ [(&str, &str)]>::None,
+            )
+            .await?
+        } else {
+            let mut query = vec![
+                ("uploader", uploader_id.to_string()),
+                ("count", self.page_size.to_string()),
+            ];
+            if let Some(created_after) = created_after {
+                query.push(("created-after", format_rfc3339(created_after)));
+            }
+            query.extend(filters.query_params());
+            self.fetch_json(
+                EndpointClass::ListReplays,
+                self.api_url("replays"),
+                Some(query.as_slice()),
+            )
+            .await?
+        };
+        Ok(ReplayPage {
+            replays: listing.list,
+            next: listing.next,
+        })
+    }
+
+    /// Fetches a single page of the child groups of `parent_id`.
+    pub async fn fetch_child_groups(
+        &self,
+        parent_id: &str,
+        cursor: Option<&str>,
+    ) -> Result<GroupPage, FetchError> {
+        self.fetch_group_page(&[("group", parent_id)], cursor).await
+    }
+
+    /// Fetches a single page of the top-level groups of `creator_id`.
+    pub async fn fetch_creator_groups(
+        &self,
+        creator_id: &str,
+        cursor: Option<&str>,
+    ) -> Result<GroupPage, FetchError> {
+        self.fetch_group_page(&[("creator", creator_id)], cursor)
+            .await
+    }
+
+    /// Fetches a single page of groups matching the `query`. The `cursor` is the `next` url
+    /// from a previous page, or `None` to fetch the first page.
+    async fn fetch_group_page(
+        &self,
+        query: &[(&str, &str)],
+        cursor: Option<&str>,
+    ) -> Result<GroupPage, FetchError> {
+        let list: GroupListing = if let Some(next_url) = cursor {
+            self.fetch_json(
+                EndpointClass::Groups,
+                next_url.to_string(),
+                Option::<&'_ [(&str, &str)]>::None,
+            )
+            .await?
+        } else {
+            let page_size = self.page_size.to_string();
+            let mut query = query.to_vec();
+            query.push(("count", page_size.as_str()));
+            self.fetch_json(
+                EndpointClass::Groups,
+                self.api_url("groups"),
+                Some(query.as_slice()),
+            )
+            .await?
+        };
+
         let groups = list
             .list
             .into_iter()
             .map(TodoGroup::from)
             .filter(|tg| !tg.is_done())
             .collect();
-        Ok(groups)
+        Ok(GroupPage {
+            groups,
+            next: list.next,
+        })
     }
 
+    /// GETs the given url and deserializes the JSON response body, retrying transient
+    /// failures with exponential backoff until `MAX_RETRY_DURATION` has elapsed.
     #[tracing::instrument(level = "debug", skip(self, query))]
     async fn fetch_json<Q: Serialize + ?Sized, T: DeserializeOwned>(
         &self,
+        class: EndpointClass,
         url: String,
         query: Option<&Q>,
-    ) -> anyhow::Result<T> {
+    ) -> Result<T, FetchError> {
+        let start = std::time::Instant::now();
+        let mut attempt: u32 = 0;
+        loop {
+            let (err, retry_after) = match self.try_fetch_json(class, &url, query).await {
+                Ok(body) => return Ok(body),
+                Err((err, _)) if !err.is_retryable() => return Err(err),
+                Err(err) => err,
+            };
+
+            let delay = retry_after.unwrap_or_else(|| backoff_delay(attempt));
+            attempt += 1;
+            let elapsed = start.elapsed();
+            if elapsed + delay > MAX_RETRY_DURATION {
+                tracing::warn!(%url, %attempt, elapsed_secs = %elapsed.as_secs(), error = ?err, "giving up on request after too many retries");
+                return Err(err);
+            }
+            tracing::warn!(%url, %attempt, delay_ms = %delay.as_millis(), error = ?err, "request failed, will retry");
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.shutdown.cancelled() => return Err(FetchError::Cancelled),
+            }
+        }
+    }
+
+    /// Makes a single attempt at fetching the given url. Errors are returned along with
+    /// the delay that was requested by the server via the `Retry-After` header, if any.
+    async fn try_fetch_json<Q: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        class: EndpointClass,
+        url: &str,
+        query: Option<&Q>,
+    ) -> Result<T, (FetchError, Option<Duration>)> {
         let Fetcher {
             client,
             auth_token,
-            rate_limiter,
+            rate_limiters,
+            ..
         } = self;
 
-        loop {
-            // Do our own rate limiting, so that we can avoid 429 responses in the common case
-            rate_limiter.until_ready().await;
+        tokio::select! {
+            _ = rate_limiters.get(class).until_ready() => {}
+            _ = self.shutdown.cancelled() => return Err((FetchError::Cancelled, None)),
+        }
 
-            let builder = client
-                .get(url.as_str())
-                .header("Authorization", auth_token.as_str());
+        let builder = client.get(url).header("Authorization", auth_token.as_str());
 
-            let builder = if let Some(q) = query {
-                builder.query(q)
-            } else {
-                builder
-            };
+        let builder = if let Some(q) = query {
+            builder.query(q)
+        } else {
+            builder
+        };
+
+        let resp = builder
+            .send()
+            .await
+            .map_err(|err| (FetchError::Transport(err), None))?;
+        let s = resp.status();
+        if s == reqwest::StatusCode::OK {
+            // Read the body separately from deserializing it, so that a connection reset
+            // partway through the body can be distinguished from an invalid body.
+            let bytes = resp
+                .bytes()
+                .await
+                .map_err(|err| (FetchError::Transport(err), None))?;
+            serde_json::from_slice(&bytes).map_err(|err| (FetchError::Decode(err), None))
+        } else {
+            let retry_after = parse_retry_after(resp.headers());
+            let body = resp
+                .text()
+                .await
+                .unwrap_or_else(|err| format!("<failed to read body: {err}>"));
+            Err((FetchError::from_status(s, body), retry_after))
+        }
+    }
+}
+
+/// An error from fetching something from the ballchasing api.
+#[derive(Debug)]
+pub enum FetchError {
+    /// The auth token was rejected (401 or 403).
+    Unauthorized {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    /// The requested resource does not exist, or no longer exists (404 or 410).
+    NotFound {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    /// The request was rejected because of rate limiting (429).
+    RateLimited { body: String },
+    /// The api returned a 5xx status.
+    Server {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    /// The api returned any other unsuccessful status.
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    /// The response body could not be deserialized.
+    Decode(serde_json::Error),
+    /// The request could not be sent, or the response could not be read.
+    Transport(reqwest::Error),
+    /// The request was never sent, or not retried, because the connector is shutting down.
+    Cancelled,
+}
+
+impl FetchError {
+    fn from_status(status: reqwest::StatusCode, body: String) -> FetchError {
+        use reqwest::StatusCode;
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                FetchError::Unauthorized { status, body }
+            }
+            StatusCode::NOT_FOUND | StatusCode::GONE => FetchError::NotFound { status, body },
+            StatusCode::TOO_MANY_REQUESTS => FetchError::RateLimited { body },
+            s if s.is_server_error() => FetchError::Server { status, body },
+            _ => FetchError::Status { status, body },
+        }
+    }
+
+    /// Returns true if the request might succeed if it's tried again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FetchError::RateLimited { .. } | FetchError::Server { .. } => true,
+            // Builder errors are the only transport errors that are certain to happen
+            // again, since they indicate a problem with the request itself.
+            FetchError::Transport(err) => !err.is_builder(),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::Unauthorized { status, body } => write!(
+                f,
+                "the ballchasing api rejected the auth token ({status}), body: {body:?}"
+            ),
+            FetchError::NotFound { status, body } => {
+                write!(f, "not found ({status}), body: {body:?}")
+            }
+            FetchError::RateLimited { body } => {
+                write!(f, "rate limited by the ballchasing api, body: {body:?}")
+            }
+            FetchError::Server { status, body } | FetchError::Status { status, body } => {
+                write!(f, "response error {status}, body: {body:?}")
+            }
+            FetchError::Decode(_) => f.write_str("deserializing response body"),
+            FetchError::Transport(_) => f.write_str("fetching url"),
+            FetchError::Cancelled => f.write_str("request cancelled by shutdown"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FetchError::Decode(err) => Some(err),
+            FetchError::Transport(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the delay before the given (zero-based) retry attempt, which doubles with each
+/// attempt up to `MAX_BACKOFF`. Half of the delay is randomized so that concurrent requests
+/// don't all retry at the same moment.
+fn backoff_delay(attempt: u32) -> Duration {
+    let max = INITIAL_BACKOFF
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_BACKOFF);
+    let half = max / 2;
+    half + half.mul_f64(rand::random::<f64>())
+}
+
+/// Parses a `Retry-After` header, which ballchasing sends as a number of seconds.
+fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn api_root_is_validated() {
+        assert_eq!(
+            BALLCHASING_API_ROOT,
+            parse_api_root(BALLCHASING_API_ROOT).unwrap()
+        );
+        assert_eq!(
+            "http://localhost:8080/api",
+            parse_api_root("http://localhost:8080/api/").unwrap()
+        );
 
-            let resp = builder.send().await.context("fetching url")?;
-            let s = resp.status();
-            if s == reqwest::StatusCode::OK {
-                let body = resp
-                    .json::<T>()
-                    .await
-                    .context("deserializing response body")?;
-                return Ok(body);
-            } else if s == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                // We'll just loop around and try again
-                tracing::debug!("delaying in response to 429 status");
-            } else {
-                let body = resp.text().await;
-                return Err(anyhow::anyhow!("response error {s:?}, body: {body:?}"));
+        for invalid in [
+            "",
+            "ballchasing.com/api",
+            "ftp://ballchasing.com/api",
+            "mailto:someone@ballchasing.com",
+            "https://ballchasing.com/api?foo=bar",
+        ] {
+            assert!(
+                parse_api_root(invalid).is_err(),
+                "expected '{invalid}' to be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn fetch_errors_are_classified_by_status() {
+        use reqwest::StatusCode;
+
+        for (status, expect_retryable) in [
+            (StatusCode::UNAUTHORIZED, false),
+            (StatusCode::FORBIDDEN, false),
+            (StatusCode::NOT_FOUND, false),
+            (StatusCode::GONE, false),
+            (StatusCode::BAD_REQUEST, false),
+            (StatusCode::TOO_MANY_REQUESTS, true),
+            (StatusCode::INTERNAL_SERVER_ERROR, true),
+            (StatusCode::BAD_GATEWAY, true),
+        ] {
+            let err = FetchError::from_status(status, String::new());
+            assert_eq!(expect_retryable, err.is_retryable(), "status: {status}");
+        }
+        assert!(matches!(
+            FetchError::from_status(StatusCode::FORBIDDEN, String::new()),
+            FetchError::Unauthorized { .. }
+        ));
+        assert!(matches!(
+            FetchError::from_status(StatusCode::GONE, String::new()),
+            FetchError::NotFound { .. }
+        ));
+    }
+
+    #[test]
+    fn backoff_delay_grows_and_is_bounded() {
+        for attempt in 0..40 {
+            let max = INITIAL_BACKOFF
+                .saturating_mul(1 << attempt.min(16))
+                .min(MAX_BACKOFF);
+            let delay = backoff_delay(attempt);
+            assert!(
+                delay >= max / 2 && delay <= max,
+                "attempt {attempt}: {delay:?}"
+            );
+        }
+        assert!(backoff_delay(0) <= INITIAL_BACKOFF);
+        assert!(backoff_delay(39) >= MAX_BACKOFF / 2);
+    }
+
+    #[tokio::test]
+    async fn waiting_requests_are_interrupted_by_shutdown() {
+        // The hourly limit only permits a single request, so the second one would
+        // otherwise wait for most of an hour, and port 9 refuses connections, so the
+        // first one would otherwise keep retrying.
+        let config: EndpointConfig = serde_json::from_value(serde_json::json!({
+            "authToken": "x",
+            "apiBaseUrl": "http://127.0.0.1:9",
+            "rateLimits": {"requestsPerHour": 1},
+        }))
+        .unwrap();
+        let mut fetcher = Fetcher::new(&config).unwrap();
+        let shutdown = CancellationToken::new();
+        fetcher.set_shutdown(shutdown.clone());
+
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                shutdown.cancel();
             }
+        });
+        for _ in 0..2 {
+            let result = tokio::time::timeout(Duration::from_secs(10), fetcher.ping_server())
+                .await
+                .expect("request must be interrupted");
+            assert!(matches!(result, Err(FetchError::Cancelled)), "{result:?}");
         }
     }
+
+    #[test]
+    fn retry_after_header_is_parsed() {
+        use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, parse_retry_after(&headers));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static(" 30"));
+        assert_eq!(Some(Duration::from_secs(30)), parse_retry_after(&headers));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(None, parse_retry_after(&headers));
+    }
 }
@@ -1,11 +1,14 @@
 pub mod fetcher;
 pub mod pull;
+pub mod rate_limit;
+pub mod schedule;
 pub mod state;
 pub mod transactor;
 
 use std::mem;
 
-use self::fetcher::Fetcher;
+use self::fetcher::{FetchError, Fetcher};
+use self::state::SweepRoot;
 use anyhow::Context;
 
 use proto_flow::capture::{
@@ -27,14 +30,384 @@ pub struct EndpointConfig {
     /// If you don't have one, get one by visiting:
     /// https://ballchasing.com/login
     auth_token: String,
+
+    /// The number of replays or groups to request per page when listing them.
+    ///
+    /// Defaults to 200, which is the maximum that's allowed by the ballchasing api.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1, max = 200))]
+    page_size: Option<u32>,
+
+    /// The maximum number of replays to fetch concurrently.
+    ///
+    /// Requests are still subject to the rate limits, so this mostly helps to hide the
+    /// latency of each request. Defaults to 4.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1, max = 32))]
+    replay_concurrency: Option<u32>,
+
+    /// How long to keep re-checking replays that ballchasing failed to process, in hours
+    /// since the replay was uploaded.
+    ///
+    /// Replays that are still pending are always re-checked. Defaults to 72 hours.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failed_replay_max_age_hours: Option<u32>,
+
+    /// Run continuously, starting a new sweep this many minutes after the previous one
+    /// started.
+    ///
+    /// If neither this nor a sweep schedule is set, the connector exits after each sweep
+    /// and relies on the runtime to restart it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1))]
+    sweep_interval_minutes: Option<u32>,
+
+    /// Run continuously, starting each sweep at the next time that matches this cron
+    /// expression, in UTC. For example, `0 */6 * * *` sweeps every 6 hours.
+    ///
+    /// Takes precedence over the sweep interval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sweep_schedule: Option<String>,
+
+    /// Base url of the ballchasing api.
+    ///
+    /// This is only useful for pointing the connector at a proxy or a stand-in
+    /// for the api. Defaults to https://ballchasing.com/api
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "advanced_url_schema")]
+    api_base_url: Option<String>,
+
+    /// Overrides for the rate limits that are otherwise determined by the patreon tier
+    /// of the account that owns the auth token.
+    #[serde(default)]
+    rate_limits: RateLimitConfig,
+}
+
+impl EndpointConfig {
+    fn failed_replay_max_age(&self) -> time::Duration {
+        time::Duration::hours(self.failed_replay_max_age_hours.unwrap_or(72).into())
+    }
+}
+
+/// Rate limits for the ballchasing api. The limits for each class of endpoint take
+/// precedence over the limits for all endpoints.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitConfig {
+    /// Limits that apply to every class of api endpoint.
+    #[serde(flatten)]
+    all: RateLimitOverride,
+
+    /// Limits for listing replays.
+    #[serde(default)]
+    list_replays: RateLimitOverride,
+
+    /// Limits for fetching a single replay.
+    #[serde(default)]
+    get_replay: RateLimitOverride,
+
+    /// Limits for listing and fetching groups.
+    #[serde(default)]
+    groups: RateLimitOverride,
+
+    /// Limits for downloading replay files.
+    #[serde(default)]
+    download_replay: RateLimitOverride,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitOverride {
+    /// Maximum number of requests to make per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1))]
+    requests_per_second: Option<u32>,
+
+    /// Maximum number of requests to make per hour, where 0 means unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requests_per_hour: Option<u32>,
+}
+
+/// Schema for optional urls that should be hidden behind the "advanced" section of the UI.
+fn advanced_url_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    let mut schema = gen.subschema_for::<Option<String>>().into_object();
+    schema.format = Some("uri".to_string());
+    schema
+        .extensions
+        .insert("advanced".to_string(), serde_json::Value::Bool(true));
+    schema.into()
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ResourceConfig {
+    /// What the binding captures. Defaults to replays.
+    #[serde(default, skip_serializing_if = "BindingKind::is_default")]
+    kind: BindingKind,
+
     /// The creator id to filter replays in ballchasing.
-    /// Only replays in groups for this creator will be ingested.
-    creator_id: String,
+    /// Only replays in groups for this creator, or uploaded by them, will be ingested.
+    /// Exactly one of `creatorId` or `groupId` must be set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    creator_id: Option<String>,
+
+    /// The id of a single group to ingest replays from, including those in any of its
+    /// subgroups. Exactly one of `creatorId` or `groupId` must be set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group_id: Option<String>,
+
+    /// Which of the creator's replays to capture. Defaults to groups.
+    #[serde(default, skip_serializing_if = "ReplaySource::is_default")]
+    source: ReplaySource,
+
+    /// Only capture the replays that match all of these filters.
+    ///
+    /// Changing the filters discards the state of the binding, so that all of the replays
+    /// which match the new filters are captured.
+    #[serde(default, skip_serializing_if = "ReplayFilters::is_empty")]
+    filters: ReplayFilters,
+
+    /// Emit a deletion document for each replay that is no longer in any of the creator's
+    /// groups.
+    ///
+    /// This requires remembering the id of every replay in the connector state, and is
+    /// only supported when capturing replays from groups.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    track_deletions: bool,
+
+    /// List the replays of every group on every sweep, even if its replay count is
+    /// unchanged.
+    ///
+    /// Otherwise, a group where one replay was removed and another added between two
+    /// sweeps is only listed again after it's been skipped for 24 sweeps, which delays
+    /// capturing the added replay and any deletion of the removed one.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    list_unchanged_groups: bool,
+}
+
+impl ResourceConfig {
+    /// Validates the combination of options, and returns what the binding sweeps over.
+    pub fn sweep_root(&self) -> anyhow::Result<SweepRoot> {
+        let root = match (&self.creator_id, &self.group_id) {
+            (Some(creator_id), None) => SweepRoot::Creator(creator_id.clone()),
+            (None, Some(group_id)) => SweepRoot::Group(group_id.clone()),
+            (Some(_), Some(_)) => anyhow::bail!("only one of creatorId or groupId may be set"),
+            (None, None) => anyhow::bail!("one of creatorId or groupId must be set"),
+        };
+        if self.filters.match_result.is_some()
+            && self.filters.player_name.is_empty()
+            && self.filters.player_id.is_empty()
+        {
+            anyhow::bail!("the matchResult filter requires a playerName or playerId filter");
+        }
+        if self.kind != BindingKind::Replays
+            && (self.source != ReplaySource::Groups
+                || self.track_deletions
+                || self.list_unchanged_groups
+                || !self.filters.is_empty())
+        {
+            anyhow::bail!(
+                "source, trackDeletions, listUnchangedGroups and filters can only be set on bindings that capture replays"
+            );
+        }
+        if self.source != ReplaySource::Groups {
+            if matches!(root, SweepRoot::Group(_)) {
+                anyhow::bail!("source must be 'groups' when capturing {root}");
+            }
+            if self.track_deletions {
+                anyhow::bail!(
+                    "trackDeletions is only supported when the source is 'groups', for {root}"
+                );
+            }
+        }
+        Ok(root)
+    }
+
+    /// The values of the `resource_path_pointers`, in order. Trailing empty values are
+    /// left out, so that bindings which only set a `creatorId` keep the same path as they
+    /// had before `groupId` and `kind` were added.
+    fn resource_path(&self) -> Vec<String> {
+        let kind = match self.kind {
+            BindingKind::Replays => "",
+            BindingKind::Groups => "groups",
+            BindingKind::GroupStats => "groupStats",
+        };
+        let mut path = vec![
+            self.creator_id.clone().unwrap_or_default(),
+            self.group_id.clone().unwrap_or_default(),
+            kind.to_string(),
+        ];
+        while path.len() > 1 && path.last().is_some_and(String::is_empty) {
+            path.pop();
+        }
+        path
+    }
+}
+
+/// The kind of documents that a binding captures.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum BindingKind {
+    /// One document per replay, with the stats of each player and team in the game.
+    #[default]
+    Replays,
+    /// One document per group, with its name, creator and settings.
+    Groups,
+    /// One document per player and one per team in each group, with their cumulative and
+    /// per-game stats over all of the group's replays.
+    GroupStats,
+}
+
+impl BindingKind {
+    pub(crate) fn is_default(&self) -> bool {
+        *self == BindingKind::default()
+    }
+}
+
+/// Filters that are passed along to ballchasing when listing replays.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayFilters {
+    /// Only capture replays from any of these playlists, such as `ranked-doubles` or
+    /// `private`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    playlist: Vec<String>,
+
+    /// Only capture replays from this season, such as `14`, or `f3` for the third season
+    /// after the game went free to play.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    season: Option<String>,
+
+    /// Only capture replays that were won or lost by the players in `playerName` or
+    /// `playerId`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    match_result: Option<MatchResult>,
+
+    /// Only capture replays with players of at least this rank, such as `champion-1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_rank: Option<String>,
+
+    /// Only capture replays with players of at most this rank, such as `diamond-3`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_rank: Option<String>,
+
+    /// Only capture replays that have at least one pro player in them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pro: bool,
+
+    /// Only capture replays played on this map, using its ballchasing map code, such as
+    /// `stadium_p`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    map: Option<String>,
+
+    /// Only capture replays with a player that has any of these names.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    player_name: Vec<String>,
+
+    /// Only capture replays with any of these players, identified by their platform and
+    /// id, such as `steam:76561198000000001`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    player_id: Vec<String>,
+
+    /// Only capture replays whose title contains this text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+
+    /// Only capture replays that were played after this time.
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(schema_with = "date_time_schema")]
+    replay_date_after: Option<time::OffsetDateTime>,
+
+    /// Only capture replays that were played before this time.
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(schema_with = "date_time_schema")]
+    replay_date_before: Option<time::OffsetDateTime>,
+}
+
+impl ReplayFilters {
+    pub fn is_empty(&self) -> bool {
+        self == &ReplayFilters::default()
+    }
+
+    /// Returns the query parameters for the ballchasing `/replays` endpoint.
+    pub fn query_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        params.extend(self.playlist.iter().map(|p| ("playlist", p.clone())));
+        params.extend(self.season.clone().map(|s| ("season", s)));
+        params.extend(self.match_result.map(|r| {
+            let result = match r {
+                MatchResult::Win => "win",
+                MatchResult::Loss => "loss",
+            };
+            ("match-result", result.to_string())
+        }));
+        params.extend(self.min_rank.clone().map(|r| ("min-rank", r)));
+        params.extend(self.max_rank.clone().map(|r| ("max-rank", r)));
+        if self.pro {
+            params.push(("pro", "true".to_string()));
+        }
+        params.extend(self.map.clone().map(|m| ("map", m)));
+        params.extend(self.player_name.iter().map(|n| ("player-name", n.clone())));
+        params.extend(self.player_id.iter().map(|id| ("player-id", id.clone())));
+        params.extend(self.title.clone().map(|t| ("title", t)));
+        params.extend(
+            self.replay_date_after
+                .map(|t| ("replay-date-after", fetcher::format_rfc3339(t))),
+        );
+        params.extend(
+            self.replay_date_before
+                .map(|t| ("replay-date-before", fetcher::format_rfc3339(t))),
+        );
+        params
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchResult {
+    Win,
+    Loss,
+}
+
+/// Schema for optional timestamps.
+fn date_time_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    let mut schema = gen.subschema_for::<Option<String>>().into_object();
+    schema.format = Some("date-time".to_string());
+    schema.into()
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplaySource {
+    /// Replays in any of the groups created by the creator, or their subgroups.
+    #[default]
+    Groups,
+    /// Replays uploaded by the creator, regardless of whether they're in a group.
+    Uploads,
+    /// Both the replays in the creator's groups and the ones they uploaded.
+    All,
+}
+
+impl ReplaySource {
+    fn is_default(&self) -> bool {
+        *self == ReplaySource::default()
+    }
+
+    pub fn includes_groups(self) -> bool {
+        self != ReplaySource::Uploads
+    }
+
+    pub fn includes_uploads(self) -> bool {
+        self != ReplaySource::Groups
+    }
 }
 
 fn schema_for<T: JsonSchema>() -> RootSchema {
@@ -58,7 +431,7 @@ pub async fn run_connector(
         open,
         ..
     } = req;
-    if let Some(_) = spec {
+    if spec.is_some() {
         return do_spec(stdout).await;
     }
     if let Some(mut discover_req) = discover {
@@ -72,7 +445,7 @@ pub async fn run_connector(
         )
         .await;
     }
-    if let Some(_) = apply {
+    if apply.is_some() {
         return do_apply(stdout).await;
     }
     if let Some(open_req) = open {
@@ -107,7 +480,13 @@ async fn do_spec(mut stdout: io::Stdout) -> anyhow::Result<()> {
             resource_config_schema_json,
             documentation_url: "https://go.estuary.dev/placeholder".to_string(),
             oauth2: None,
-            resource_path_pointers: vec!["/creatorId".to_string()],
+            // Only `/creatorId` existed originally, so new pointers must only be appended,
+            // and must be absent from the resource configs of existing bindings.
+            resource_path_pointers: vec![
+                "/creatorId".to_string(),
+                "/groupId".to_string(),
+                "/kind".to_string(),
+            ],
         }),
         ..Default::default()
     };
@@ -119,13 +498,14 @@ async fn do_discover(config: String, mut stdout: io::Stdout) -> anyhow::Result<(
     let endpoint_config =
         serde_json::from_str::<EndpointConfig>(&config).context("parsing endpoint config")?;
 
-    let fetcher = Fetcher::new(endpoint_config.auth_token);
-    let ping_response = fetcher
-        .ping_server()
-        .await
-        .context("failed to connect to ballchasing api")?;
+    let fetcher = Fetcher::new(&endpoint_config)?;
+    let ping_response = fetcher.ping_server().await.map_err(ping_error)?;
 
-    let bindings = vec![discovered_collection(ping_response.steam_id)];
+    let bindings = vec![
+        discovered_collection(ping_response.steam_id.clone()),
+        discovered_groups_collection(ping_response.steam_id.clone()),
+        discovered_group_stats_collection(ping_response.steam_id),
+    ];
     let response = Response {
         discovered: Some(Discovered { bindings }),
         ..Default::default()
@@ -140,27 +520,48 @@ async fn do_validate(
 ) -> anyhow::Result<()> {
     let endpoint_config =
         serde_json::from_str::<EndpointConfig>(&config).context("deserializing endpoint config")?;
-    let fetcher = Fetcher::new(endpoint_config.auth_token);
-    let ping_response = fetcher
-        .ping_server()
-        .await
-        .context("failed to connect to ballchasing api")?;
+    schedule::SweepSchedule::from_config(&endpoint_config)?;
+    let fetcher = Fetcher::new(&endpoint_config)?;
+    let ping_response = fetcher.ping_server().await.map_err(ping_error)?;
     tracing::info!(?ping_response, "successfully pinged the ballchasing API");
     let mut output = Vec::with_capacity(bindings.len());
     for binding in bindings {
         let resource_config = serde_json::from_str::<ResourceConfig>(&binding.resource_config_json)
             .context("deserializing resource config")?;
+        let root = resource_config.sweep_root()?;
 
-        let groups = fetcher
-            .fetch_creator_groups(&resource_config.creator_id)
-            .await
-            .context("fetching groups for creator_id")?;
-        tracing::info!(num_groups = %groups.len(), creator_id = %resource_config.creator_id, "fetched groups for creator");
-
-        let resource_path = vec![resource_config.creator_id];
+        match &root {
+            SweepRoot::Creator(creator_id) => {
+                if resource_config.source.includes_groups() {
+                    let page = fetcher
+                        .fetch_creator_groups(creator_id, None)
+                        .await
+                        .context("fetching groups for creator_id")?;
+                    tracing::info!(num_groups = %page.groups.len(), has_more = page.next.is_some(), %creator_id, "fetched groups for creator");
+                }
+                if resource_config.source.includes_uploads() {
+                    let page = fetcher
+                        .fetch_uploaded_replays(creator_id, None, &resource_config.filters, None)
+                        .await
+                        .context("fetching replays uploaded by creator_id")?;
+                    tracing::info!(num_replays = %page.replays.len(), has_more = page.next.is_some(), %creator_id, "fetched replays uploaded by creator");
+                }
+            }
+            SweepRoot::Group(group_id) => {
+                let group = fetcher.fetch_group(group_id).await.map_err(|err| match err {
+                    FetchError::NotFound { .. } | FetchError::Unauthorized { .. } => {
+                        anyhow::Error::new(err).context(format!(
+                            "group {group_id} does not exist, or is not readable with this auth token"
+                        ))
+                    }
+                    other => anyhow::Error::new(other).context("fetching group for group_id"),
+                })?;
+                tracing::info!(?group, "fetched group");
+            }
+        }
 
         output.push(ValidatedBinding {
-            resource_path: resource_path.clone(),
+            resource_path: resource_config.resource_path(),
         });
     }
 
@@ -171,17 +572,37 @@ async fn do_validate(
     write_capture_response(response, &mut stdout).await
 }
 
+/// Turns an error from pinging the api into one that explains the likely cause to the user.
+fn ping_error(err: FetchError) -> anyhow::Error {
+    match err {
+        FetchError::Unauthorized { .. } => anyhow::Error::new(err).context(
+            "the ballchasing auth token is invalid, please check that it was copied correctly",
+        ),
+        other => anyhow::Error::new(other).context("failed to connect to ballchasing api"),
+    }
+}
+
 pub async fn read_capture_request(stdin: &mut io::BufReader<io::Stdin>) -> anyhow::Result<Request> {
+    match read_next_request(stdin).await? {
+        Some(req) => Ok(req),
+        None => anyhow::bail!("unexpected EOF reading request from stdin"),
+    }
+}
+
+/// Reads the next request from stdin, returning `None` if stdin has been closed.
+pub async fn read_next_request(
+    stdin: &mut io::BufReader<io::Stdin>,
+) -> anyhow::Result<Option<Request>> {
     let mut buf = String::with_capacity(4096);
     stdin
         .read_line(&mut buf)
         .await
         .context("reading next request line")?;
     if buf.trim().is_empty() {
-        anyhow::bail!("unexpected EOF reading request from stdin");
+        return Ok(None);
     }
     let deser = serde_json::from_str(&buf).context("deserializing request")?;
-    Ok(deser)
+    Ok(Some(deser))
 }
 
 /// Writes the response to stdout, and waits to a flush to complete. The flush ensures that the complete
@@ -207,7 +628,8 @@ fn discovered_collection(steam_id: String) -> DiscoveredBinding {
         disable: false,
         recommended_name: "replays".to_string(),
         resource_config_json: serde_json::to_string(&ResourceConfig {
-            creator_id: steam_id,
+            creator_id: Some(steam_id),
+            ..Default::default()
         })
         .unwrap(),
         document_schema_json: serde_json::to_string(&serde_json::json!({
@@ -217,8 +639,67 @@ fn discovered_collection(steam_id: String) -> DiscoveredBinding {
                 "_meta": {
                     "type": "object",
                     "properties": {
+                        "op": {
+                            "description": "Set to 'd' when the replay has been deleted",
+                            "type": "string",
+                            "enum": ["d"]
+                        },
                         "parent_groups": {
+                            "description": "Every group that the replay was found in, along with their ancestors",
+                            "type": "array",
+                            "items": { "$ref": "#/$defs/group" }
+                        },
+                        "lineages": {
+                            "description": "The path from a top-level group to each group that the replay was found in",
+                            "type": "array",
+                            "items": {
+                                "type": "array",
+                                "items": { "$ref": "#/$defs/group" }
+                            }
+                        }
+                    }
+                },
+                "id": { "type": "string" }
+            },
+            "required": ["_meta", "id"],
+            "$defs": {
+                "group": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string" },
+                        "name": { "type": "string" }
+                    },
+                    "required": ["name", "id"]
+                }
+            }
+        }))
+        .unwrap(),
+        key: vec!["/id".to_string()],
+        resource_path: Vec::new(), // resource_path is deprecated and unused
+    }
+}
 
+fn discovered_groups_collection(steam_id: String) -> DiscoveredBinding {
+    DiscoveredBinding {
+        // Each binding walks the whole tree of groups on its own, which would use up the
+        // hourly limit for groups several times as quickly, so this is opt-in.
+        disable: true,
+        recommended_name: "groups".to_string(),
+        resource_config_json: serde_json::to_string(&ResourceConfig {
+            kind: BindingKind::Groups,
+            creator_id: Some(steam_id),
+            ..Default::default()
+        })
+        .unwrap(),
+        document_schema_json: serde_json::to_string(&serde_json::json!({
+            "type": "object",
+            "x-infer-schema": true,
+            "properties": {
+                "_meta": {
+                    "type": "object",
+                    "properties": {
+                        "parent_groups": {
+                            "description": "The ancestors of the group, starting with its top-level group",
                             "type": "array",
                             "items": {
                                 "type": "object",
@@ -231,7 +712,8 @@ fn discovered_collection(steam_id: String) -> DiscoveredBinding {
                         }
                     }
                 },
-                "id": { "type": "string" }
+                "id": { "type": "string" },
+                "name": { "type": "string" }
             },
             "required": ["_meta", "id"]
         }))
@@ -241,6 +723,61 @@ fn discovered_collection(steam_id: String) -> DiscoveredBinding {
     }
 }
 
+fn discovered_group_stats_collection(steam_id: String) -> DiscoveredBinding {
+    DiscoveredBinding {
+        // Opt-in for the same reason as the groups binding.
+        disable: true,
+        recommended_name: "group_stats".to_string(),
+        resource_config_json: serde_json::to_string(&ResourceConfig {
+            kind: BindingKind::GroupStats,
+            creator_id: Some(steam_id),
+            ..Default::default()
+        })
+        .unwrap(),
+        document_schema_json: serde_json::to_string(&serde_json::json!({
+            "type": "object",
+            "x-infer-schema": true,
+            "properties": {
+                "_meta": {
+                    "type": "object",
+                    "properties": {
+                        "group_name": { "type": "string" },
+                        "parent_groups": {
+                            "description": "The ancestors of the group, starting with its top-level group",
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "id": { "type": "string" },
+                                    "name": { "type": "string" }
+                                },
+                                "required": ["name", "id"]
+                            }
+                        }
+                    }
+                },
+                "group_id": { "type": "string" },
+                "type": {
+                    "type": "string",
+                    "enum": ["player", "team"]
+                },
+                "id": {
+                    "description": "The platform and id of the player, like `steam:123`, or the name of the team",
+                    "type": "string"
+                }
+            },
+            "required": ["_meta", "group_id", "type", "id"]
+        }))
+        .unwrap(),
+        key: vec![
+            "/group_id".to_string(),
+            "/type".to_string(),
+            "/id".to_string(),
+        ],
+        resource_path: Vec::new(), // resource_path is deprecated and unused
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -256,4 +793,94 @@ mod test {
         let schema = schema_for::<ResourceConfig>();
         insta::assert_json_snapshot!(schema);
     }
+    #[test]
+    fn replay_filters_are_passed_as_query_params() {
+        let filters: ReplayFilters = serde_json::from_value(serde_json::json!({
+            "playlist": ["ranked-doubles", "private"],
+            "matchResult": "win",
+            "pro": true,
+            "playerId": ["steam:76561198000000001"],
+            "replayDateAfter": "2023-09-01T00:00:00Z",
+        }))
+        .unwrap();
+        let params: Vec<(&str, String)> = vec![
+            ("playlist", "ranked-doubles".to_string()),
+            ("playlist", "private".to_string()),
+            ("match-result", "win".to_string()),
+            ("pro", "true".to_string()),
+            ("player-id", "steam:76561198000000001".to_string()),
+            ("replay-date-after", "2023-09-01T00:00:00Z".to_string()),
+        ];
+        assert_eq!(filters.query_params(), params);
+        assert!(ReplayFilters::default().query_params().is_empty());
+    }
+
+    #[test]
+    fn resource_paths_are_unique_and_compatible() {
+        let path = |json: serde_json::Value| {
+            serde_json::from_value::<ResourceConfig>(json)
+                .unwrap()
+                .resource_path()
+        };
+        // Bindings from before `groupId` and `kind` were added keep their original path.
+        assert_eq!(vec!["123"], path(serde_json::json!({"creatorId": "123"})));
+        assert_eq!(
+            vec!["123"],
+            path(serde_json::json!({"creatorId": "123", "source": "all"}))
+        );
+        assert_eq!(
+            vec!["", "league"],
+            path(serde_json::json!({"groupId": "league"}))
+        );
+        assert_eq!(
+            vec!["123", "", "groups"],
+            path(serde_json::json!({"creatorId": "123", "kind": "groups"}))
+        );
+        assert_eq!(
+            vec!["", "league", "groupStats"],
+            path(serde_json::json!({"groupId": "league", "kind": "groupStats"}))
+        );
+    }
+
+    #[test]
+    fn resource_config_sweep_root_is_validated() {
+        let root = |json: serde_json::Value| {
+            serde_json::from_value::<ResourceConfig>(json)
+                .unwrap()
+                .sweep_root()
+                .map_err(|err| err.to_string())
+        };
+        assert_eq!(
+            root(serde_json::json!({"creatorId": "123"})),
+            Ok(SweepRoot::Creator("123".to_string()))
+        );
+        assert_eq!(
+            root(serde_json::json!({"groupId": "league"})),
+            Ok(SweepRoot::Group("league".to_string()))
+        );
+        assert!(root(serde_json::json!({})).is_err());
+        assert!(root(serde_json::json!({"creatorId": "123", "groupId": "league"})).is_err());
+        assert!(root(serde_json::json!({"groupId": "league", "source": "all"})).is_err());
+        assert!(root(
+            serde_json::json!({"creatorId": "123", "source": "uploads", "trackDeletions": true})
+        )
+        .is_err());
+        assert_eq!(
+            root(serde_json::json!({"groupId": "league", "kind": "groups"})),
+            Ok(SweepRoot::Group("league".to_string()))
+        );
+        assert!(root(
+            serde_json::json!({"creatorId": "123", "kind": "groups", "filters": {"pro": true}})
+        )
+        .is_err());
+        assert!(root(
+            serde_json::json!({"creatorId": "123", "kind": "groups", "trackDeletions": true})
+        )
+        .is_err());
+        assert!(root(serde_json::json!({"creatorId": "123", "listUnchangedGroups": true})).is_ok());
+        assert!(root(
+            serde_json::json!({"groupId": "league", "kind": "groupStats", "listUnchangedGroups": true})
+        )
+        .is_err());
+    }
 }
@@ -1,23 +1,27 @@
 use crate::{
-    fetcher::{Fetcher, ReplaySummary, Visibility},
-    state::{BindingState, State, TodoGroup},
-    write_capture_response, EndpointConfig, ResourceConfig,
+    fetcher::{FetchError, Fetcher, ReplayStatus, ReplaySummary, Visibility},
+    ping_error,
+    schedule::SweepSchedule,
+    state::{
+        binding_key, BindingState, GroupReplays, KnownGroup, ParentGroup, PendingReplay, State,
+        SweepReplay, SweepRoot, TodoGroup,
+    },
+    write_capture_response, BindingKind, EndpointConfig, ResourceConfig,
 };
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-use crate::transactor::Emitter;
+use crate::transactor::{Acknowledgements, Emitter};
 use anyhow::Context;
+use futures::StreamExt;
 use proto_flow::capture::{request::Open, response::Opened, Response};
-use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use time::OffsetDateTime;
-use tokio::io;
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ParentGroup {
-    id: String,
-    name: String,
-}
+use tokio::{
+    io,
+    sync::{oneshot, watch},
+};
+use tokio_util::sync::CancellationToken;
 
 pub async fn do_pull(
     Open {
@@ -25,7 +29,7 @@ pub async fn do_pull(
         state_json,
         ..
     }: Open,
-    _stdin: io::BufReader<io::Stdin>,
+    stdin: io::BufReader<io::Stdin>,
     mut stdout: io::Stdout,
 ) -> anyhow::Result<()> {
     tracing::info!("starting to pull");
@@ -36,15 +40,14 @@ pub async fn do_pull(
     let config = serde_json::from_str::<EndpointConfig>(&spec.config_json)
         .context("deserializing endpoint config")?;
 
-    let fetcher = Fetcher::new(config.auth_token);
+    let schedule = SweepSchedule::from_config(&config)?;
+    let mut fetcher = Fetcher::new(&config)?;
+    let ping_response = fetcher.ping_server().await.map_err(ping_error)?;
+    fetcher.set_account_tier(ping_response.tier);
 
-    let mut state: State = if state_json.trim().is_empty() {
-        State::default()
-    } else {
-        serde_json::from_str(&state_json).context("deserializing state checkpoint")?
-    };
+    let mut state = State::from_checkpoint(&state_json)?;
 
-    let mut binding_indices = BTreeMap::new();
+    let mut bindings = BTreeMap::new();
     for (i, binding) in spec.bindings.iter().enumerate() {
         let collection_name = binding
             .collection
@@ -54,19 +57,48 @@ pub async fn do_pull(
         let resource_config: ResourceConfig = serde_json::from_str(&binding.resource_config_json)
             .context("deserializing resource config")?;
 
-        let binding_key = format!("{};{}", resource_config.creator_id, collection_name);
-        if !state.bindings.contains_key(&binding_key) {
-            tracing::info!(%binding_key, "initializing new empty state for binding");
-            state.bindings.insert(
-                binding_key.clone(),
-                BindingState::new(collection_name, &resource_config.creator_id),
-            );
+        let root = resource_config.sweep_root()?;
+        let binding_key = binding_key(&root, collection_name);
+        match state.bindings.get_mut(&binding_key) {
+            Some(existing)
+                if existing.backfill.is_none_or(|b| b == binding.backfill)
+                    && existing.kind == resource_config.kind
+                    && existing.filters == resource_config.filters =>
+            {
+                existing.backfill = Some(binding.backfill);
+            }
+            existing => {
+                match existing {
+                    Some(existing) if existing.backfill != Some(binding.backfill) => {
+                        tracing::info!(%binding_key, previous_backfill = ?existing.backfill, backfill = binding.backfill, "backfill counter changed, discarding state for binding");
+                    }
+                    Some(existing) if existing.kind != resource_config.kind => {
+                        tracing::info!(%binding_key, previous_kind = ?existing.kind, kind = ?resource_config.kind, "binding kind changed, discarding state for binding");
+                    }
+                    Some(existing) => {
+                        tracing::info!(%binding_key, previous_filters = ?existing.filters, filters = ?resource_config.filters, "replay filters changed, discarding state for binding");
+                    }
+                    None => {
+                        tracing::info!(%binding_key, "initializing new empty state for binding");
+                    }
+                }
+                state.bindings.insert(
+                    binding_key.clone(),
+                    BindingState::new(collection_name, root, binding.backfill, &resource_config),
+                );
+            }
         }
-        binding_indices.insert(binding_key, i as u32);
+        bindings.insert(
+            binding_key,
+            Binding {
+                index: i as u32,
+                resource_config,
+            },
+        );
     }
 
     state.bindings.retain(|k, v| {
-        if binding_indices.contains_key(k) {
+        if bindings.contains_key(k) {
             true
         } else {
             tracing::info!(binding_key = %k, state = ?v, "clearing out unused state");
@@ -82,60 +114,331 @@ pub async fn do_pull(
     };
     write_capture_response(resp, &mut stdout).await?;
 
-    let mut emitter = Emitter(stdout);
+    // The sweeps are stopped early, but gracefully, if the runtime closes stdin or sends
+    // SIGTERM, so that a consistent checkpoint can be committed before exiting. Requests
+    // that are already in flight are allowed to finish, but any that would have to wait
+    // on a rate limit or a retry fail with `FetchError::Cancelled` instead, which ends
+    // the current batch of documents early.
+    let shutdown = CancellationToken::new();
+    spawn_signal_handler(shutdown.clone())?;
+    fetcher.set_shutdown(shutdown.clone());
+
+    // The runtime sends an acknowledgement of each checkpoint, which are read on a
+    // separate task so that we can bound the number of outstanding checkpoints. That
+    // task exits once stdin is closed, which means that the runtime wants us to stop.
+    // Its result is sent before `shutdown` is cancelled, so that it's always available
+    // once the sweeps have stopped because of it.
+    let (acked_tx, acked_rx) = watch::channel(0);
+    let (acks_result_tx, mut acks_result_rx) = oneshot::channel();
+    let acks_task = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            let result = Acknowledgements(stdin).run(acked_tx).await;
+            let _ = acks_result_tx.send(result);
+            shutdown.cancel();
+        }
+    });
+    let mut emitter = Emitter::new(stdout, acked_rx);
+
+    let ctx = SweepContext {
+        bindings,
+        config,
+        fetcher,
+        caller_steam_id: ping_response.steam_id,
+        shutdown,
+    };
+    let result = match run_sweeps(&ctx, schedule.as_ref(), &mut state, &mut emitter).await {
+        // Batches of documents are committed when they're cut short, but a cancelled request
+        // while starting a sweep or listing groups leaves the state partway through a page,
+        // so it's discarded in favor of the last checkpoint that was committed.
+        Err(err) if matches!(err.downcast_ref(), Some(FetchError::Cancelled)) => {
+            tracing::info!("sweep was interrupted by shutdown");
+            Ok(())
+        }
+        result => result,
+    };
+
+    tracing::info!("shutting down");
+    // An error reading acknowledgements is what caused the sweeps to stop, so it takes
+    // precedence over any error from the sweeps themselves.
+    match acks_result_rx.try_recv() {
+        Ok(acks_result) => acks_result.context("reading acknowledgements")?,
+        Err(oneshot::error::TryRecvError::Closed) => {
+            acks_task.await.context("acknowledgements task panicked")?;
+        }
+        Err(oneshot::error::TryRecvError::Empty) => acks_task.abort(),
+    }
+    result
+}
+
+/// Runs sweeps until `ctx.shutdown` is cancelled, or just a single sweep if there's no
+/// `schedule`.
+async fn run_sweeps(
+    ctx: &SweepContext,
+    schedule: Option<&SweepSchedule>,
+    state: &mut State,
+    emitter: &mut Emitter,
+) -> anyhow::Result<()> {
+    loop {
+        if let Some(schedule) = schedule {
+            let delay = time_until_next_sweep(state, schedule)?;
+            if !delay.is_zero() {
+                tracing::info!(delay_secs = %delay.as_secs(), "waiting for next sweep");
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = ctx.shutdown.cancelled() => {}
+                }
+            }
+        }
+        if ctx.shutdown.is_cancelled() {
+            return Ok(());
+        }
+
+        run_sweep(ctx, state, emitter).await?;
+
+        if schedule.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+/// Cancels `shutdown` when the process receives SIGTERM or SIGINT.
+fn spawn_signal_handler(shutdown: CancellationToken) -> anyhow::Result<()> {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .context("installing SIGTERM handler")?;
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => tracing::info!("received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => tracing::info!("received SIGINT"),
+        }
+        shutdown.cancel();
+    });
+    Ok(())
+}
+
+/// A binding of the capture, along with its position in the capture spec.
+struct Binding {
+    index: u32,
+    resource_config: ResourceConfig,
+}
+
+/// The maximum number of replays or groups to emit before committing a checkpoint.
+const DOCUMENTS_PER_CHECKPOINT: usize = 50;
+
+/// The number of `sweep_replays` that a binding can pile up while listing its groups,
+/// before they're fetched without waiting for the rest of the groups to be listed.
+const MAX_SWEEP_REPLAYS: usize = 1000;
+
+/// The things that stay the same across every sweep.
+struct SweepContext {
+    /// The bindings of the capture, keyed on the same keys as `State::bindings`.
+    bindings: BTreeMap<String, Binding>,
+    config: EndpointConfig,
+    fetcher: Fetcher,
+    caller_steam_id: String,
+    /// Cancelled when the connector should stop as soon as it can do so gracefully.
+    shutdown: CancellationToken,
+}
 
-    run_sweep(binding_indices, &mut state, &fetcher, &mut emitter).await
+/// Returns how long to wait before starting the next sweep, which is zero if a sweep is
+/// already in progress, or if any binding has never completed one. If there are no
+/// bindings at all, the next sweep is scheduled as if one had just started.
+fn time_until_next_sweep(
+    state: &State,
+    schedule: &SweepSchedule,
+) -> anyhow::Result<std::time::Duration> {
+    let mut last_sweep: Option<OffsetDateTime> = None;
+    for binding_state in state.bindings.values() {
+        match (
+            binding_state.sweep_start,
+            binding_state.last_completed_sweep,
+        ) {
+            (None, Some(completed)) => {
+                last_sweep = Some(last_sweep.map_or(completed, |ls| ls.min(completed)));
+            }
+            _ => return Ok(std::time::Duration::ZERO),
+        }
+    }
+    let now = OffsetDateTime::now_utc();
+    let next_sweep = schedule.next_sweep(last_sweep.unwrap_or(now))?;
+    Ok((next_sweep - now)
+        .try_into()
+        .unwrap_or(std::time::Duration::ZERO))
 }
 
+/// Runs a sweep to completion, or until `ctx.shutdown` is cancelled, committing the
+/// state after each batch of replays.
 async fn run_sweep(
-    binding_indices: BTreeMap<String, u32>,
+    ctx: &SweepContext,
     state: &mut State,
-    fetcher: &Fetcher,
     emitter: &mut Emitter,
 ) -> anyhow::Result<()> {
-    let ping_response = fetcher
-        .ping_server()
-        .await
-        .context("failed to ping server")?;
-    let caller_steam_id = ping_response.steam_id;
+    let SweepContext {
+        bindings,
+        config,
+        fetcher,
+        caller_steam_id,
+        shutdown,
+    } = ctx;
 
-    // Is there an in-progress sweep? If not, then we'll start one.
-    for binding_state in state.bindings.values_mut() {
-        if binding_state.sweep_start.is_none() {
-            binding_state.start_sweep(fetcher).await?;
+    // Is there an in-progress sweep? If not, then we'll start one. Pending replays are
+    // re-checked first, while their lineages are still those of the last sweep.
+    for (binding_key, binding_state) in state.bindings.iter_mut() {
+        if binding_state.sweep_start.is_none() && !shutdown.is_cancelled() {
+            let binding = bindings.get(binding_key).unwrap();
+            recheck_pending_replays(
+                binding_state,
+                binding.index,
+                config.failed_replay_max_age(),
+                fetcher,
+                emitter,
+                shutdown,
+            )
+            .await
+            .context("re-checking pending replays")?;
+            if shutdown.is_cancelled() {
+                break;
+            }
+            binding_state
+                .start_sweep(fetcher, binding.resource_config.source)
+                .await?;
         }
     }
 
+    if shutdown.is_cancelled() {
+        emitter.commit_state(&*state).await?;
+        return Ok(());
+    }
+
     tracing::debug!("runnning sweep");
 
+    // First list all of the groups, so that we know every group that each new replay is in.
+    // If too many replays pile up along the way, they're fetched without waiting for the
+    // rest of the groups, and any that are found in another group later on are listed again.
     while state.bindings.values().any(|b| !b.is_sweep_complete()) {
         for (binding_key, binding_state) in state.bindings.iter_mut() {
-            if binding_state.is_sweep_complete() {
+            if binding_state.is_sweep_complete() || shutdown.is_cancelled() {
                 continue;
             }
             tracing::debug!(%binding_key, ?binding_state, todo_groups = binding_state.todo_groups.len(), "checking for next replays");
-            if let Some((lineage, replays)) =
-                next_replays(binding_state, fetcher, &caller_steam_id).await?
-            {
-                tracing::debug!(%binding_key, ?lineage, num_replays = replays.len(), "found replays to fetch");
-                let binding_idx = binding_indices.get(binding_key).unwrap();
-                ingest_replays(lineage, *binding_idx, &replays, fetcher, emitter)
-                    .await
-                    .context("ingesting replays")?;
-                tracing::debug!(%binding_key, num_replays = replays.len(), "finished processing replays");
+            let binding = bindings.get(binding_key).unwrap();
+            let num_new = if binding_state.must_list_uploads {
+                list_next_uploads(binding_state, fetcher, caller_steam_id).await?
             } else {
-                tracing::debug!("no replays found under group");
-            }
+                list_next_replays(
+                    binding_state,
+                    binding.resource_config.list_unchanged_groups,
+                    fetcher,
+                    caller_steam_id,
+                )
+                .await?
+            };
+            tracing::debug!(%binding_key, num_new, num_sweep_replays = binding_state.sweep_replays.len(), "finished listing groups");
         }
         tracing::debug!("persisting state");
-        emitter.commit(&*state, false).await?;
+        emitter.commit_state(&*state).await?;
+
+        for (binding_key, binding) in bindings.iter() {
+            loop {
+                let binding_state = state.bindings.get_mut(binding_key).unwrap();
+                if binding_state.is_sweep_complete()
+                    || binding_state.sweep_replays.len() < MAX_SWEEP_REPLAYS
+                    || shutdown.is_cancelled()
+                {
+                    break;
+                }
+                tracing::info!(%binding_key, num_remaining = binding_state.sweep_replays.len(), "fetching replays before all groups are listed");
+                ingest_replays(
+                    binding.index,
+                    binding_state,
+                    DOCUMENTS_PER_CHECKPOINT,
+                    fetcher,
+                    emitter,
+                    shutdown,
+                )
+                .await
+                .context("ingesting replays")?;
+                emitter.commit_state(&*state).await?;
+            }
+        }
+
+        if shutdown.is_cancelled() {
+            tracing::info!("stopping sweep early due to shutdown");
+            return Ok(());
+        }
     }
-    tracing::debug!("sweep complete, pending state update");
+
     for binding_state in state.bindings.values_mut() {
-        binding_state.last_completed_sweep = binding_state.sweep_start.take();
+        binding_state.finish_listing();
+    }
+
+    // Then fetch each of the new replays and changed groups exactly once. The remaining
+    // ones are persisted in each checkpoint, so a restart picks up right where the last
+    // checkpoint left off.
+    for (binding_key, binding) in bindings.iter() {
+        loop {
+            let binding_state = state.bindings.get_mut(binding_key).unwrap();
+            if !binding_state.captured_groups.sweep.is_empty() {
+                tracing::info!(%binding_key, num_remaining = binding_state.captured_groups.sweep.len(), "fetching changed groups");
+                ingest_groups(
+                    binding.index,
+                    binding_state,
+                    DOCUMENTS_PER_CHECKPOINT,
+                    fetcher,
+                    emitter,
+                    shutdown,
+                )
+                .await
+                .context("ingesting groups")?;
+            } else if !binding_state.sweep_replays.is_empty() {
+                tracing::info!(%binding_key, num_remaining = binding_state.sweep_replays.len(), "fetching new replays");
+                ingest_replays(
+                    binding.index,
+                    binding_state,
+                    DOCUMENTS_PER_CHECKPOINT,
+                    fetcher,
+                    emitter,
+                    shutdown,
+                )
+                .await
+                .context("ingesting replays")?;
+            } else {
+                break;
+            }
+            emitter.commit_state(&*state).await?;
+
+            if shutdown.is_cancelled() {
+                tracing::info!("stopping sweep early due to shutdown");
+                return Ok(());
+            }
+        }
+    }
+
+    tracing::debug!("sweep complete, pending state update");
+    for (binding_key, binding_state) in state.bindings.iter_mut() {
+        let binding = bindings.get(binding_key).unwrap();
+        let deleted = binding_state.complete_sweep(&binding.resource_config);
+        if !deleted.replays.is_empty() {
+            tracing::info!(%binding_key, num_deleted = deleted.replays.len(), "emitting deletions for replays that are no longer in any group");
+        }
+        for replay_id in deleted.replays {
+            let doc = serde_json::json!({
+                "id": replay_id,
+                "_meta": { "op": "d" },
+            });
+            emitter.emit_doc(binding.index, &doc).await?;
+        }
+        if !deleted.groups.is_empty() {
+            tracing::info!(%binding_key, num_deleted = deleted.groups.len(), "emitting deletions for groups that were not seen during the sweep");
+        }
+        for (group_id, group) in deleted.groups {
+            for doc in group_deletion_docs(binding_state.kind, &group_id, &group) {
+                emitter.emit_doc(binding.index, &doc).await?;
+            }
+        }
     }
 
-    emitter.commit(&*state, false).await?;
+    emitter.commit_state(&*state).await?;
 
     Ok(())
 }
@@ -147,49 +450,124 @@ fn lineage_info(grp: &TodoGroup) -> ParentGroup {
     }
 }
 
-fn should_ingest(
+/// Returns true if the caller has permission to download the replay.
+fn can_download(replay: &ReplaySummary, caller_steam_id: &str) -> bool {
+    let is_public = replay.visibility.unwrap_or(Visibility::Public) == Visibility::Public;
+    if is_public || replay.uploader.steam_id == caller_steam_id {
+        true
+    } else {
+        tracing::warn!(?replay, %caller_steam_id, "skipping replay because it is not public and does not belong to the caller");
+        false
+    }
+}
+
+/// Returns true if the replay has not yet been captured from this group. Replays are
+/// identified as new by comparing against the replays that were in the group the last time
+/// it was listed, if we know them. Otherwise, everything is new to the first sweep, and
+/// after that replays are new if they were uploaded after the last sweep and haven't been
+/// emitted since, or if the group itself was created after the last sweep.
+fn is_new_replay(
     last_completed_sweep: Option<OffsetDateTime>,
+    group: &TodoGroup,
+    known: Option<&GroupReplays>,
+    recent_replays: &BTreeMap<String, OffsetDateTime>,
     replay: &ReplaySummary,
-    caller_steam_id: &str,
 ) -> bool {
-    // Filter out replays that we've already captured
-    if !last_completed_sweep
-        .map(|sc| replay.created > sc)
-        .unwrap_or(true)
-    {
+    if let Some(known) = known {
+        return !known.replay_ids.contains(&replay.id);
+    }
+    let Some(last_sweep) = last_completed_sweep else {
+        return true;
+    };
+    if recent_replays.contains_key(&replay.id) {
         return false;
     }
+    replay.created > last_sweep || group.created.is_some_and(|c| c > last_sweep)
+}
 
-    // Filter out replays that we don't have permission to download
-    if replay.visibility.unwrap_or(Visibility::Public) == Visibility::Public {
-        true
-    } else if replay.uploader.steam_id == caller_steam_id {
-        true
-    } else {
-        tracing::warn!(?replay, %caller_steam_id, "skipping replay because it is not public and does not belong to the caller");
-        false
+/// Lists the next page of replays uploaded by the creator, adding any new ones to
+/// `sweep_replays`. Returns the number of replays that were added.
+#[tracing::instrument(skip(fetcher), level = "debug")]
+async fn list_next_uploads(
+    state: &mut BindingState,
+    fetcher: &Fetcher,
+    caller_steam_id: &str,
+) -> anyhow::Result<usize> {
+    let SweepRoot::Creator(creator_id) = &state.root else {
+        anyhow::bail!("only creators have uploaded replays, not {}", state.root);
+    };
+    let page = fetcher
+        .fetch_uploaded_replays(
+            creator_id,
+            state.uploads_watermark,
+            &state.filters,
+            state.uploads_cursor.as_deref(),
+        )
+        .await
+        .context("listing uploaded replays")?;
+    tracing::info!(%creator_id, num_replays = page.replays.len(), has_more = page.next.is_some(), "fetched replays uploaded by creator");
+    state.must_list_uploads = page.next.is_some();
+    state.uploads_cursor = page.next;
+
+    let mut num_new = 0;
+    for rp in page.replays {
+        if state.recent_replays.contains_key(&rp.id)
+            || state.pending_replays.contains_key(&rp.id)
+            || !can_download(&rp, caller_steam_id)
+        {
+            continue;
+        }
+        // Replays that are also in a group get its lineage once they're emitted.
+        state.sweep_replays.entry(rp.id).or_insert_with(|| {
+            num_new += 1;
+            SweepReplay {
+                created: rp.created,
+            }
+        });
     }
+    Ok(num_new)
 }
 
-/// Does a depth-first search of the graph of groups. Does not use recursion
-/// because async rust does not yet allow it
+/// Does a depth-first search of the graph of groups, down to the next group that has no
+/// children left to list, adding any new replays to `sweep_replays`. Returns the number of
+/// replays that were added. Groups whose replay count hasn't changed are skipped, unless
+/// `list_unchanged_groups` is set. Does not use recursion because async rust does not yet
+/// allow it
 #[tracing::instrument(skip(fetcher), level = "debug")]
-async fn next_replays(
+async fn list_next_replays(
     state: &mut BindingState,
+    list_unchanged_groups: bool,
     fetcher: &Fetcher,
     caller_steam_id: &str,
-) -> anyhow::Result<Option<(Vec<ParentGroup>, Vec<ReplaySummary>)>> {
+) -> anyhow::Result<usize> {
+    state.todo_groups.retain(|g| !g.is_done());
+    if state.todo_groups.is_empty() && state.groups_cursor.is_some() {
+        state
+            .fetch_next_groups(fetcher)
+            .await
+            .context("fetching next page of creator groups")?;
+    }
+
     let BindingState {
+        sweep_start,
         last_completed_sweep,
+        kind,
         todo_groups,
+        filters,
+        sweep_replays,
+        early_replays,
+        recent_replays,
+        pending_replays,
+        group_replays,
+        captured_groups,
         ..
     } = state;
-
-    todo_groups.retain(|g| !g.is_done());
+    let sweep_start = sweep_start.expect("sweep must be in progress");
 
     let Some(grp) = todo_groups.front_mut() else {
-        return Ok(None);
+        return Ok(0);
     };
+    let mut num_new = 0;
 
     let mut next_group: &mut TodoGroup = grp;
     let mut lineage = vec![lineage_info(&*next_group)];
@@ -198,25 +576,81 @@ async fn next_replays(
         // Does this group contain direct replays?
         if next_group.must_fetch_replays {
             next_group.must_fetch_replays = false;
-            let mut replays = fetcher.fetch_replay_ids(&next_group.id).await?;
-            replays.retain(|rp| should_ingest(*last_completed_sweep, rp, caller_steam_id));
-            if !replays.is_empty() {
-                return Ok(Some((lineage, replays)));
+            // The group only needs to be listed if its replays have changed since the last
+            // time we listed it.
+            let unchanged = group_replays
+                .get_mut(&next_group.id)
+                .filter(|k| !list_unchanged_groups && k.can_skip(next_group.direct_replays));
+            if let Some(known) = unchanged {
+                tracing::debug!(group_id = %next_group.id, "skipping group with unchanged replays");
+                known.last_seen_sweep = sweep_start;
+                known.lineage = lineage.clone();
+                known.skipped_sweeps += 1;
+                for replay_id in &known.replay_ids {
+                    if let Some(created) = early_replays.remove(replay_id) {
+                        sweep_replays.insert(replay_id.clone(), SweepReplay { created });
+                    }
+                }
+            } else {
+                let replays = fetcher
+                    .fetch_replay_ids(&next_group.id, filters)
+                    .await
+                    .context("listing replays")?;
+                let all_replays = GroupReplays {
+                    direct_replays: next_group.direct_replays.unwrap_or(replays.len() as i64),
+                    replay_ids: replays.iter().map(|r| r.id.clone()).collect(),
+                    lineage: lineage.clone(),
+                    last_seen_sweep: sweep_start,
+                    skipped_sweeps: 0,
+                };
+                let known = group_replays.get(&next_group.id);
+                for rp in replays {
+                    if let Some(created) = early_replays.remove(&rp.id) {
+                        sweep_replays.insert(rp.id, SweepReplay { created });
+                        continue;
+                    }
+                    if pending_replays.contains_key(&rp.id)
+                        || !is_new_replay(
+                            *last_completed_sweep,
+                            next_group,
+                            known,
+                            recent_replays,
+                            &rp,
+                        )
+                        || !can_download(&rp, caller_steam_id)
+                    {
+                        continue;
+                    }
+                    // Replays that are in multiple groups are only fetched once. Replays that
+                    // were already emitted from other groups are emitted again, with the
+                    // lineages of all of their groups, rather than just this one.
+                    sweep_replays.entry(rp.id).or_insert_with(|| {
+                        num_new += 1;
+                        SweepReplay {
+                            created: rp.created,
+                        }
+                    });
+                }
+                group_replays.insert(next_group.id.clone(), all_replays);
             }
         }
-        // Does this group maybe have any children?
-        if next_group.must_fetch_children {
-            let children = fetcher
-                .fetch_child_groups(&next_group.id)
+        // Does this group maybe have any more children? The next page is only fetched once
+        // all the children from the previous page are done, which keeps the state small.
+        next_group.children.retain(|g| !g.is_done());
+        if next_group.children.is_empty() && next_group.must_fetch_children {
+            let page = fetcher
+                .fetch_child_groups(&next_group.id, next_group.children_cursor.as_deref())
                 .await
                 .context("fetching child groups")?;
-            next_group.must_fetch_children = false;
-            next_group.children.extend(children);
+            next_group.must_fetch_children = page.next.is_some();
+            next_group.children_cursor = page.next;
+            let mut groups = page.groups;
+            captured_groups.visit(*kind, Some(sweep_start), &mut groups, &lineage);
+            next_group.children.extend(groups);
         }
 
-        next_group.children.retain(|g| !g.is_done());
         if next_group.children.is_empty() {
-            return Ok(None);
+            return Ok(num_new);
         } else {
             // Descend into DFS of the child
             next_group = next_group.children.front_mut().unwrap();
@@ -225,28 +659,515 @@ async fn next_replays(
     }
 }
 
+/// The outcome of fetching a single replay.
+enum Fetched {
+    /// The replay is ready to be emitted.
+    Ready(Value),
+    /// The replay hasn't been successfully processed by ballchasing yet.
+    NotReady(ReplayStatus),
+    /// The replay no longer exists.
+    Gone,
+}
+
+fn check_fetched(replay_id: &str, result: Result<Value, FetchError>) -> anyhow::Result<Fetched> {
+    match result {
+        Ok(replay_json) => {
+            // Replays that don't report a status are assumed to be ready.
+            let status = replay_json
+                .get("status")
+                .and_then(|s| serde_json::from_value(s.clone()).ok())
+                .unwrap_or(ReplayStatus::Ok);
+            if status == ReplayStatus::Ok {
+                Ok(Fetched::Ready(replay_json))
+            } else {
+                Ok(Fetched::NotReady(status))
+            }
+        }
+        Err(err @ FetchError::NotFound { .. }) => {
+            tracing::info!(%replay_id, error = ?err, "skipping replay that no longer exists");
+            Ok(Fetched::Gone)
+        }
+        Err(err) => {
+            tracing::warn!(%replay_id, error = ?err, "failed to fetch replay");
+            Err(anyhow::Error::new(err).context("fetching replay"))
+        }
+    }
+}
+
+/// Returns each of the groups in any of the lineages, without duplicates.
+fn parent_groups(lineages: &[Vec<ParentGroup>]) -> Vec<&ParentGroup> {
+    let mut seen = BTreeSet::new();
+    lineages
+        .iter()
+        .flatten()
+        .filter(|g| seen.insert(g.id.as_str()))
+        .collect()
+}
+
+async fn emit_replay(
+    mut replay_json: Value,
+    lineages: &[Vec<ParentGroup>],
+    binding: u32,
+    emitter: &mut Emitter,
+) -> anyhow::Result<()> {
+    replay_json
+        .as_object_mut()
+        .expect("replay must be an object")
+        .insert(
+            "_meta".to_string(),
+            serde_json::json!({
+                "parent_groups": parent_groups(lineages),
+                "lineages": lineages,
+            }),
+        );
+    emitter.emit_doc(binding, &replay_json).await
+}
+
+/// Fetches and emits up to `limit` of the replays in `sweep_replays`, removing them as
+/// they're processed. Replays that have been deleted since they were listed are skipped, and
+/// those that aren't ready yet are added to `pending_replays` so they can be re-checked
+/// on the next sweep. Stops early if `shutdown` is cancelled, leaving the rest of the
+/// replays, including any whose requests were cancelled, for when the connector restarts.
 async fn ingest_replays(
-    lineage: Vec<ParentGroup>,
     binding: u32,
-    replays: &[ReplaySummary],
+    state: &mut BindingState,
+    limit: usize,
     fetcher: &Fetcher,
     emitter: &mut Emitter,
+    shutdown: &CancellationToken,
 ) -> anyhow::Result<()> {
-    let meta = serde_json::json!({ "parent_groups": lineage });
+    // Replays are fetched concurrently, but they're yielded in order so that the documents
+    // in each checkpoint are deterministic.
+    let replay_ids: Vec<String> = state.sweep_replays.keys().take(limit).cloned().collect();
+    let mut fetched = std::pin::pin!(fetcher.fetch_replays(replay_ids));
+    while let Some((replay_id, result)) = fetched.next().await {
+        if matches!(result, Err(FetchError::Cancelled)) {
+            break;
+        }
+        let fetched = check_fetched(&replay_id, result)?;
+        let replay = state
+            .sweep_replays
+            .remove(&replay_id)
+            .expect("sweep replay must exist");
+        match fetched {
+            Fetched::Ready(replay_json) => {
+                let lineages = state.replay_lineages(&replay_id);
+                emit_replay(replay_json, &lineages, binding, emitter).await?;
+                state.record_emitted(&replay_id, replay.created);
+            }
+            Fetched::NotReady(status) => {
+                tracing::info!(%binding, %replay_id, ?status, "deferring replay that is not yet processed");
+                state.pending_replays.insert(
+                    replay_id,
+                    PendingReplay {
+                        created: replay.created,
+                        status,
+                    },
+                );
+            }
+            Fetched::Gone => {}
+        }
+        if shutdown.is_cancelled() {
+            break;
+        }
+    }
+    Ok(())
+}
 
-    for replay in replays {
-        let mut replay_json = match fetcher.fetch_replay(&replay.id).await {
-            Ok(rp) => rp,
+/// Fetches the details of up to `limit` of the groups in `captured_groups.sweep`, and emits
+/// either the group itself or the stats of its players and teams, depending on the kind of
+/// the binding. Groups are removed as they're processed, and ones that have been deleted
+/// since they were listed are skipped. Stops early if `shutdown` is cancelled.
+async fn ingest_groups(
+    binding: u32,
+    state: &mut BindingState,
+    limit: usize,
+    fetcher: &Fetcher,
+    emitter: &mut Emitter,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<()> {
+    let group_ids: Vec<String> = state
+        .captured_groups
+        .sweep
+        .keys()
+        .take(limit)
+        .cloned()
+        .collect();
+    for group_id in group_ids {
+        let result = fetcher.fetch_group_details(&group_id).await;
+        if matches!(result, Err(FetchError::Cancelled)) {
+            break;
+        }
+        let group = state
+            .captured_groups
+            .sweep
+            .remove(&group_id)
+            .expect("sweep group must exist");
+        match result {
+            Ok(group_json) if state.kind == BindingKind::GroupStats => {
+                let mut stats_ids = BTreeMap::<String, BTreeSet<String>>::new();
+                for doc in group_stats_docs(&group_id, group_json, &group.parent_groups) {
+                    if let (Some(Value::String(stats_type)), Some(Value::String(id))) =
+                        (doc.get("type"), doc.get("id"))
+                    {
+                        stats_ids
+                            .entry(stats_type.clone())
+                            .or_default()
+                            .insert(id.clone());
+                    }
+                    emitter.emit_doc(binding, &doc).await?;
+                }
+                if let Some(known) = state.captured_groups.known.get_mut(&group_id) {
+                    let previous = std::mem::replace(&mut known.stats_ids, stats_ids);
+                    for doc in stats_deletion_docs(&group_id, &previous, &known.stats_ids) {
+                        emitter.emit_doc(binding, &doc).await?;
+                    }
+                }
+            }
+            Ok(mut doc) => {
+                // The stats of the group's players and teams are cumulative over all of its
+                // replays, which would make for some very large documents.
+                doc.remove("players");
+                doc.remove("teams");
+                doc.insert(
+                    "_meta".to_string(),
+                    serde_json::json!({ "parent_groups": group.parent_groups }),
+                );
+                emitter.emit_doc(binding, &doc).await?;
+            }
+            Err(err @ FetchError::NotFound { .. }) => {
+                tracing::info!(%group_id, error = ?err, "group no longer exists");
+                if let Some(known) = state.captured_groups.known.remove(&group_id) {
+                    for doc in group_deletion_docs(state.kind, &group_id, &known) {
+                        emitter.emit_doc(binding, &doc).await?;
+                    }
+                }
+            }
             Err(err) => {
-                tracing::warn!(?lineage, ?binding, ?replay, error = ?err, "failed to fetch replay");
-                return Err(err);
+                return Err(anyhow::Error::new(err).context(format!("fetching group {group_id}")))
             }
+        }
+        if shutdown.is_cancelled() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the deletion documents for a captured group that no longer exists, which for
+/// bindings that capture group stats is one for each of the group's players and teams.
+fn group_deletion_docs(kind: BindingKind, group_id: &str, group: &KnownGroup) -> Vec<Value> {
+    match kind {
+        BindingKind::Groups => vec![serde_json::json!({
+            "id": group_id,
+            "_meta": { "op": "d" },
+        })],
+        BindingKind::GroupStats => {
+            stats_deletion_docs(group_id, &group.stats_ids, &BTreeMap::new())
+        }
+        BindingKind::Replays => Vec::new(),
+    }
+}
+
+/// Returns a deletion document for each of the `previous` stats of a group that are not
+/// in its `current` stats, such as a player who is no longer in any of its replays.
+fn stats_deletion_docs(
+    group_id: &str,
+    previous: &BTreeMap<String, BTreeSet<String>>,
+    current: &BTreeMap<String, BTreeSet<String>>,
+) -> Vec<Value> {
+    previous
+        .iter()
+        .flat_map(|(stats_type, ids)| {
+            let current = current.get(stats_type);
+            ids.iter()
+                .filter(move |id| current.is_none_or(|c| !c.contains(*id)))
+                .map(move |id| {
+                    serde_json::json!({
+                        "group_id": group_id,
+                        "type": stats_type,
+                        "id": id,
+                        "_meta": { "op": "d" },
+                    })
+                })
+        })
+        .collect()
+}
+
+/// Splits the details of a group into one document for each of its players and teams. Each
+/// is keyed on the group id, the type, and an id that's unique within the group: the
+/// platform and id of a player, like `steam:123`, or the name of a team. Ballchasing doesn't
+/// give teams an id, so a team that's renamed gets a new key, and the old one is deleted.
+fn group_stats_docs(
+    group_id: &str,
+    mut group_json: serde_json::Map<String, Value>,
+    parent_groups: &[ParentGroup],
+) -> Vec<Value> {
+    let meta = serde_json::json!({
+        "group_name": group_json.get("name"),
+        "parent_groups": parent_groups,
+    });
+    let mut take = |field: &str| match group_json.get_mut(field).map(Value::take) {
+        Some(Value::Array(items)) => items,
+        _ => Vec::new(),
+    };
+    let (players, teams) = (take("players"), take("teams"));
+
+    let players = players.into_iter().map(|player| {
+        let id = match (player.get("platform"), player.get("id")) {
+            (Some(Value::String(platform)), Some(Value::String(id))) => format!("{platform}:{id}"),
+            _ => String::new(),
         };
-        replay_json
-            .as_object_mut()
-            .expect("replay must be an object")
-            .insert("_meta".to_string(), meta.clone());
-        emitter.emit_doc(binding, &replay_json).await?;
+        ("player", id, player)
+    });
+    let teams = teams.into_iter().map(|team| {
+        let id = team
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        ("team", id, team)
+    });
+    players
+        .chain(teams)
+        .filter_map(|(stats_type, id, mut doc)| {
+            if id.is_empty() {
+                tracing::warn!(%group_id, %stats_type, "skipping group stats without an id");
+                return None;
+            }
+            let fields = doc.as_object_mut()?;
+            fields.insert("group_id".to_string(), Value::String(group_id.to_string()));
+            fields.insert("type".to_string(), Value::String(stats_type.to_string()));
+            fields.insert("id".to_string(), Value::String(id));
+            fields.insert("_meta".to_string(), meta.clone());
+            Some(doc)
+        })
+        .collect()
+}
+
+/// Fetches each of the replays that were not yet processed during a previous sweep, and
+/// emits the ones that are now ready. Replays that ballchasing failed to process are
+/// given up on once they're older than `failed_max_age`.
+async fn recheck_pending_replays(
+    state: &mut BindingState,
+    binding: u32,
+    failed_max_age: time::Duration,
+    fetcher: &Fetcher,
+    emitter: &mut Emitter,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<()> {
+    if state.pending_replays.is_empty() {
+        return Ok(());
+    }
+    tracing::info!(root = %state.root, num_pending = state.pending_replays.len(), "re-checking pending replays");
+
+    let replay_ids: Vec<String> = state.pending_replays.keys().cloned().collect();
+    let mut fetched = std::pin::pin!(fetcher.fetch_replays(replay_ids));
+    while let Some((replay_id, result)) = fetched.next().await {
+        if matches!(result, Err(FetchError::Cancelled)) {
+            break;
+        }
+        let fetched = check_fetched(&replay_id, result)?;
+        let lineages = state.replay_lineages(&replay_id);
+        let pending = state
+            .pending_replays
+            .get_mut(&replay_id)
+            .expect("pending replay must exist");
+        match fetched {
+            Fetched::Ready(replay_json) => {
+                emit_replay(replay_json, &lineages, binding, emitter).await?;
+                let created = pending.created;
+                state.pending_replays.remove(&replay_id);
+                state.record_emitted(&replay_id, created);
+            }
+            Fetched::NotReady(ReplayStatus::Failed)
+                if OffsetDateTime::now_utc() - pending.created > failed_max_age =>
+            {
+                tracing::warn!(%replay_id, ?pending, "giving up on replay that ballchasing failed to process");
+                state.pending_replays.remove(&replay_id);
+            }
+            Fetched::NotReady(status) => {
+                pending.status = status;
+            }
+            Fetched::Gone => {
+                state.pending_replays.remove(&replay_id);
+            }
+        }
+        if shutdown.is_cancelled() {
+            break;
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fetcher::Uploader;
+    use time::macros::datetime;
+
+    fn replay(id: &str, created: OffsetDateTime) -> ReplaySummary {
+        ReplaySummary {
+            id: id.to_string(),
+            created,
+            visibility: None,
+            uploader: Uploader {
+                steam_id: "uploader".to_string(),
+            },
+        }
+    }
+
+    fn group(created: Option<OffsetDateTime>) -> TodoGroup {
+        TodoGroup {
+            id: "group".to_string(),
+            name: "group".to_string(),
+            created,
+            direct_replays: Some(1),
+            indirect_replays: None,
+            must_fetch_children: false,
+            children_cursor: None,
+            must_fetch_replays: true,
+            children: Default::default(),
+        }
+    }
+
+    #[test]
+    fn replays_added_to_known_groups_are_new_regardless_of_upload_time() {
+        let last_sweep = Some(datetime!(2023-09-01 00:00 UTC));
+        let old = replay("old", datetime!(2023-01-01 00:00 UTC));
+        let recent = replay("recent", datetime!(2023-09-02 00:00 UTC));
+        let none = BTreeMap::new();
+        let emitted = BTreeMap::from([("recent".to_string(), recent.created)]);
+
+        // Without a previous sweep, everything is new, even if it was already emitted.
+        assert!(is_new_replay(None, &group(None), None, &none, &old));
+        assert!(is_new_replay(None, &group(None), None, &emitted, &recent));
+
+        // Unknown groups fall back to comparing upload times, unless the group itself is new,
+        // and recent replays that were already emitted aren't new to them.
+        assert!(!is_new_replay(last_sweep, &group(None), None, &none, &old));
+        assert!(is_new_replay(
+            last_sweep,
+            &group(None),
+            None,
+            &none,
+            &recent
+        ));
+        assert!(!is_new_replay(
+            last_sweep,
+            &group(None),
+            None,
+            &emitted,
+            &recent
+        ));
+        let new_group = group(Some(datetime!(2023-09-03 00:00 UTC)));
+        assert!(is_new_replay(last_sweep, &new_group, None, &none, &old));
+
+        // Known groups only compare against the replays that were previously listed, so a
+        // recent replay that was emitted from another group is new to them.
+        let known = GroupReplays {
+            direct_replays: 1,
+            replay_ids: ["old".to_string()].into_iter().collect(),
+            lineage: Vec::new(),
+            last_seen_sweep: datetime!(2023-09-01 00:00 UTC),
+            skipped_sweeps: 0,
+        };
+        let known = Some(&known);
+        assert!(!is_new_replay(last_sweep, &group(None), known, &none, &old));
+        assert!(is_new_replay(
+            last_sweep,
+            &group(None),
+            known,
+            &emitted,
+            &recent
+        ));
+    }
+
+    #[test]
+    fn parent_groups_include_every_lineage_once() {
+        let group = |id: &str| ParentGroup {
+            id: id.to_string(),
+            name: format!("{id} name"),
+        };
+        let lineages = vec![
+            vec![group("league"), group("week 1")],
+            vec![group("league"), group("playoffs")],
+            vec![group("highlights")],
+        ];
+        let ids: Vec<&str> = parent_groups(&lineages)
+            .into_iter()
+            .map(|g| g.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["league", "week 1", "playoffs", "highlights"]);
+    }
+
+    #[test]
+    fn sweeps_without_bindings_wait_for_the_schedule() {
+        let state = State::default();
+        let schedule = SweepSchedule::Interval(time::Duration::minutes(60));
+        let delay = time_until_next_sweep(&state, &schedule).unwrap();
+        assert!(delay > std::time::Duration::from_secs(59 * 60), "{delay:?}");
+    }
+
+    #[test]
+    fn group_stats_are_split_into_player_and_team_docs() {
+        let serde_json::Value::Object(group) = serde_json::json!({
+            "id": "week-1",
+            "name": "Week 1",
+            "players": [
+                {"platform": "steam", "id": "123", "name": "alice", "team": "Blue", "cumulative": {"games": 3}},
+                {"platform": "epic", "id": "456", "name": "bob", "team": "Orange", "cumulative": {"games": 2}},
+            ],
+            "teams": [
+                {"name": "Blue", "players": [{"platform": "steam", "id": "123"}], "cumulative": {"wins": 2}},
+            ],
+        }) else {
+            unreachable!("group must be an object");
+        };
+        let parents = vec![ParentGroup {
+            id: "league".to_string(),
+            name: "League".to_string(),
+        }];
+        let docs = group_stats_docs("week-1", group, &parents);
+        let keys: Vec<_> = docs
+            .iter()
+            .map(|d| (d["group_id"].as_str(), d["type"].as_str(), d["id"].as_str()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (Some("week-1"), Some("player"), Some("steam:123")),
+                (Some("week-1"), Some("player"), Some("epic:456")),
+                (Some("week-1"), Some("team"), Some("Blue")),
+            ]
+        );
+        assert_eq!(docs[0]["cumulative"]["games"], 3);
+        assert_eq!(docs[2]["_meta"]["group_name"], "Week 1");
+        assert_eq!(docs[2]["_meta"]["parent_groups"][0]["id"], "league");
+    }
+
+    #[test]
+    fn stats_that_are_gone_are_deleted() {
+        let ids = |players: &[&str], teams: &[&str]| {
+            let set = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect();
+            BTreeMap::from([
+                ("player".to_string(), set(players)),
+                ("team".to_string(), set(teams)),
+            ])
+        };
+        let previous = ids(&["steam:123", "epic:456"], &["Blue"]);
+        let current = ids(&["steam:123"], &["Blue Renamed"]);
+        let deleted: Vec<_> = stats_deletion_docs("week-1", &previous, &current)
+            .into_iter()
+            .map(|d| (d["type"].clone(), d["id"].clone(), d["_meta"]["op"].clone()))
+            .collect();
+        assert_eq!(
+            deleted,
+            vec![
+                ("player".into(), "epic:456".into(), "d".into()),
+                ("team".into(), "Blue".into(), "d".into()),
+            ]
+        );
+        assert!(stats_deletion_docs("week-1", &current, &current).is_empty());
+    }
+}
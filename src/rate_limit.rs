@@ -0,0 +1,200 @@
+use std::num::NonZeroU32;
+
+use crate::{fetcher::PatreonTier, RateLimitConfig, RateLimitOverride};
+use governor::{DefaultDirectRateLimiter, Quota};
+
+/// The classes of api endpoints, which each have their own rate limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointClass {
+    ListReplays,
+    GetReplay,
+    Groups,
+    DownloadReplay,
+    /// The api root, which is used to check the auth token and has no documented limits
+    /// of its own.
+    Ping,
+}
+
+/// The request rates that are permitted by the ballchasing api.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    pub per_second: NonZeroU32,
+    /// The hourly limit, which is `None` for tiers that don't have one.
+    pub per_hour: Option<NonZeroU32>,
+}
+
+impl RateLimits {
+    /// Returns the limits that ballchasing enforces for the given class of endpoint, for
+    /// accounts of the given tier, as documented at https://ballchasing.com/doc/api
+    pub fn for_tier(tier: PatreonTier, class: EndpointClass) -> RateLimits {
+        use EndpointClass::*;
+        use PatreonTier::*;
+
+        let per_second = match tier {
+            Regular | Gold | Other => 2,
+            Diamond => 4,
+            Champion => 8,
+            Gc => 16,
+        };
+        let per_hour = match (class, tier) {
+            (ListReplays, Regular | Other) => 500,
+            (ListReplays, Gold) => 1000,
+            (ListReplays, Diamond) => 2000,
+            (GetReplay | Groups, Regular | Other) => 1000,
+            (GetReplay | Groups, Gold) => 2000,
+            (GetReplay | Groups, Diamond) => 5000,
+            (DownloadReplay, Regular | Other) => 200,
+            (DownloadReplay, Gold) => 500,
+            (DownloadReplay, Diamond) => 1000,
+            (DownloadReplay, Champion) => 2000,
+            _ => 0,
+        };
+        RateLimits {
+            per_second: NonZeroU32::new(per_second).unwrap(),
+            per_hour: NonZeroU32::new(per_hour),
+        }
+    }
+
+    /// Returns a copy of these limits with any values from the override taking precedence.
+    pub fn with_overrides(self, config: &RateLimitOverride) -> RateLimits {
+        RateLimits {
+            per_second: config
+                .requests_per_second
+                .and_then(NonZeroU32::new)
+                .unwrap_or(self.per_second),
+            per_hour: match config.requests_per_hour {
+                Some(n) => NonZeroU32::new(n),
+                None => self.per_hour,
+            },
+        }
+    }
+}
+
+/// Enforces both the per-second and per-hour limits, so that we can avoid 429 responses
+/// in the common case.
+pub struct RateLimiter {
+    per_second: DefaultDirectRateLimiter,
+    per_hour: Option<DefaultDirectRateLimiter>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> RateLimiter {
+        RateLimiter {
+            per_second: DefaultDirectRateLimiter::direct(Quota::per_second(limits.per_second)),
+            per_hour: limits
+                .per_hour
+                .map(|n| DefaultDirectRateLimiter::direct(Quota::per_hour(n))),
+        }
+    }
+
+    /// Waits until a request is permitted by all of the limits.
+    pub async fn until_ready(&self) {
+        if let Some(per_hour) = self.per_hour.as_ref() {
+            per_hour.until_ready().await;
+        }
+        self.per_second.until_ready().await;
+    }
+}
+
+/// A separate `RateLimiter` for each class of endpoint, so that a burst of requests to
+/// one class doesn't starve the others.
+pub struct RateLimiters {
+    list_replays: RateLimiter,
+    get_replay: RateLimiter,
+    groups: RateLimiter,
+    download_replay: RateLimiter,
+    ping: RateLimiter,
+}
+
+impl RateLimiters {
+    pub fn new(tier: PatreonTier, config: &RateLimitConfig) -> RateLimiters {
+        let limiter = |class| {
+            let limits = effective_limits(tier, class, config);
+            tracing::debug!(?tier, ?class, ?limits, "configured rate limits");
+            RateLimiter::new(limits)
+        };
+        RateLimiters {
+            list_replays: limiter(EndpointClass::ListReplays),
+            get_replay: limiter(EndpointClass::GetReplay),
+            groups: limiter(EndpointClass::Groups),
+            download_replay: limiter(EndpointClass::DownloadReplay),
+            ping: limiter(EndpointClass::Ping),
+        }
+    }
+
+    pub fn get(&self, class: EndpointClass) -> &RateLimiter {
+        match class {
+            EndpointClass::ListReplays => &self.list_replays,
+            EndpointClass::GetReplay => &self.get_replay,
+            EndpointClass::Groups => &self.groups,
+            EndpointClass::DownloadReplay => &self.download_replay,
+            EndpointClass::Ping => &self.ping,
+        }
+    }
+}
+
+/// Returns the limits for the given tier and class of endpoint, after applying the
+/// overrides for all endpoints, and then those for the specific class.
+pub fn effective_limits(
+    tier: PatreonTier,
+    class: EndpointClass,
+    config: &RateLimitConfig,
+) -> RateLimits {
+    let limits = RateLimits::for_tier(tier, class).with_overrides(&config.all);
+    match class {
+        EndpointClass::ListReplays => limits.with_overrides(&config.list_replays),
+        EndpointClass::GetReplay => limits.with_overrides(&config.get_replay),
+        EndpointClass::Groups => limits.with_overrides(&config.groups),
+        EndpointClass::DownloadReplay => limits.with_overrides(&config.download_replay),
+        EndpointClass::Ping => limits,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overrides_take_precedence_over_tier_limits() {
+        let regular = RateLimits::for_tier(PatreonTier::Regular, EndpointClass::ListReplays);
+        assert_eq!(
+            regular,
+            regular.with_overrides(&RateLimitOverride::default())
+        );
+
+        let overridden = regular.with_overrides(&RateLimitOverride {
+            requests_per_second: Some(5),
+            requests_per_hour: Some(0),
+        });
+        assert_eq!(5, overridden.per_second.get());
+        assert_eq!(None, overridden.per_hour);
+
+        let champion = RateLimits::for_tier(PatreonTier::Champion, EndpointClass::GetReplay);
+        let overridden = champion.with_overrides(&RateLimitOverride {
+            requests_per_second: None,
+            requests_per_hour: Some(3600),
+        });
+        assert_eq!(champion.per_second, overridden.per_second);
+        assert_eq!(Some(3600), overridden.per_hour.map(NonZeroU32::get));
+    }
+
+    #[test]
+    fn class_overrides_take_precedence_over_general_overrides() {
+        let config: RateLimitConfig = serde_json::from_value(serde_json::json!({
+            "requestsPerSecond": 3,
+            "groups": { "requestsPerSecond": 1, "requestsPerHour": 100 }
+        }))
+        .unwrap();
+
+        let groups = effective_limits(PatreonTier::Gold, EndpointClass::Groups, &config);
+        assert_eq!(1, groups.per_second.get());
+        assert_eq!(Some(100), groups.per_hour.map(NonZeroU32::get));
+
+        let get_replay = effective_limits(PatreonTier::Gold, EndpointClass::GetReplay, &config);
+        assert_eq!(3, get_replay.per_second.get());
+        assert_eq!(
+            RateLimits::for_tier(PatreonTier::Gold, EndpointClass::GetReplay).per_hour,
+            get_replay.per_hour
+        );
+    }
+}
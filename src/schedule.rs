@@ -0,0 +1,101 @@
+use crate::EndpointConfig;
+use anyhow::Context;
+use chrono::TimeZone;
+use time::OffsetDateTime;
+
+/// Determines when each sweep should start when the connector runs continuously.
+#[derive(Debug)]
+pub enum SweepSchedule {
+    /// Each sweep starts a fixed duration after the previous one started.
+    Interval(time::Duration),
+    /// Each sweep starts at the next time that matches a cron expression, in UTC.
+    Cron(Box<croner::Cron>),
+}
+
+impl SweepSchedule {
+    /// Returns the schedule from the endpoint config, or `None` if the connector should
+    /// exit after a single sweep.
+    pub fn from_config(config: &EndpointConfig) -> anyhow::Result<Option<SweepSchedule>> {
+        if let Some(expr) = config.sweep_schedule.as_deref() {
+            let cron = croner::Cron::new(expr)
+                .parse()
+                .with_context(|| format!("invalid sweep schedule '{expr}'"))?;
+            return Ok(Some(SweepSchedule::Cron(Box::new(cron))));
+        }
+        match config.sweep_interval_minutes {
+            Some(0) => anyhow::bail!("sweep interval must be at least 1 minute"),
+            Some(minutes) => Ok(Some(SweepSchedule::Interval(time::Duration::minutes(
+                minutes.into(),
+            )))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns when the sweep after one that started at `last_sweep` should start.
+    pub fn next_sweep(&self, last_sweep: OffsetDateTime) -> anyhow::Result<OffsetDateTime> {
+        match self {
+            SweepSchedule::Interval(interval) => Ok(last_sweep + *interval),
+            SweepSchedule::Cron(cron) => {
+                let after = chrono::Utc
+                    .timestamp_opt(last_sweep.unix_timestamp(), last_sweep.nanosecond())
+                    .single()
+                    .context("converting sweep timestamp")?;
+                let next = cron
+                    .find_next_occurrence(&after, false)
+                    .context("finding next scheduled sweep")?;
+                OffsetDateTime::from_unix_timestamp(next.timestamp())
+                    .context("converting next sweep timestamp")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    fn schedule(config: serde_json::Value) -> anyhow::Result<Option<SweepSchedule>> {
+        let config: EndpointConfig = serde_json::from_value(config).unwrap();
+        SweepSchedule::from_config(&config)
+    }
+
+    #[test]
+    fn schedule_is_parsed_from_config() {
+        assert!(schedule(serde_json::json!({"authToken": "x"}))
+            .unwrap()
+            .is_none());
+        assert!(
+            schedule(serde_json::json!({"authToken": "x", "sweepIntervalMinutes": 0})).is_err()
+        );
+        assert!(
+            schedule(serde_json::json!({"authToken": "x", "sweepSchedule": "not cron"})).is_err()
+        );
+    }
+
+    #[test]
+    fn next_sweep_is_computed_from_last_sweep() {
+        let last = datetime!(2023-09-01 10:00:05 UTC);
+
+        let interval = schedule(serde_json::json!({"authToken": "x", "sweepIntervalMinutes": 90}))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            datetime!(2023-09-01 11:30:05 UTC),
+            interval.next_sweep(last).unwrap()
+        );
+
+        // The schedule takes precedence over the interval
+        let cron = schedule(serde_json::json!({
+            "authToken": "x",
+            "sweepIntervalMinutes": 90,
+            "sweepSchedule": "0 */6 * * *",
+        }))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            datetime!(2023-09-01 12:00:00 UTC),
+            cron.next_sweep(last).unwrap()
+        );
+    }
+}
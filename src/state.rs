@@ -1,64 +1,553 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
-use crate::fetcher::{Fetcher, GroupSummary};
+use crate::fetcher::{Fetcher, GroupSummary, ReplayStatus};
+use crate::{BindingKind, ReplayFilters, ReplaySource, ResourceConfig};
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::OffsetDateTime;
 
+/// The current version of the state checkpoint format. Checkpoints written by older versions
+/// of the connector are upgraded by `State::from_checkpoint`, using `MIGRATIONS`.
+pub const STATE_VERSION: u32 = 2;
+
+/// Upgrades a checkpoint from each version to the next. The migration at index `n` upgrades
+/// a checkpoint from version `n` to version `n + 1`.
+const MIGRATIONS: &[fn(&mut Value) -> anyhow::Result<()>] = &[migrate_v0_to_v1, migrate_v1_to_v2];
+
+/// Returns the key of a binding's state. The state is keyed on the combination of the
+/// root of the sweep and the Flow collection name, so that we can easily throw away the
+/// state and start over if either of those things changes.
+pub fn binding_key(root: &SweepRoot, collection_name: &str) -> String {
+    format!("{root};{collection_name}")
+}
+
+/// What a binding sweeps over.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepRoot {
+    /// All of the groups created by the creator with this id, and their uploaded replays.
+    Creator(String),
+    /// The group with this id, and all of its descendants.
+    Group(String),
+}
+
+impl std::fmt::Display for SweepRoot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SweepRoot::Creator(id) => write!(f, "creator:{id}"),
+            SweepRoot::Group(id) => write!(f, "group:{id}"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BindingState {
     pub collection_name: String,
-    pub creator_id: String,
+    pub root: SweepRoot,
+    /// What the binding captures. The state is discarded whenever this changes.
+    #[serde(default, skip_serializing_if = "BindingKind::is_default")]
+    pub kind: BindingKind,
+    /// The backfill counter of the binding when this state was created. The state is
+    /// discarded whenever the counter changes, so that everything is captured again.
+    /// Checkpoints from before the counter was tracked don't have one, and adopt the
+    /// current counter of the binding instead of being discarded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backfill: Option<u32>,
+    /// The replay filters of the binding when this state was created. The state is
+    /// discarded whenever they change, since replays that match the new filters may have
+    /// been skipped by the old ones.
+    #[serde(default, skip_serializing_if = "ReplayFilters::is_empty")]
+    pub filters: ReplayFilters,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sweep_start: Option<OffsetDateTime>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub last_completed_sweep: Option<OffsetDateTime>,
-    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    #[serde(
+        default,
+        with = "groups_by_id",
+        skip_serializing_if = "VecDeque::is_empty"
+    )]
     pub todo_groups: VecDeque<TodoGroup>,
+    /// The url of the next page of top-level groups for the creator, if there are more
+    /// groups that haven't yet been added to `todo_groups`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups_cursor: Option<String>,
+    /// Whether the replays uploaded by the creator still need to be listed during the
+    /// current sweep.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub must_list_uploads: bool,
+    /// The url of the next page of replays uploaded by the creator, if some but not all of
+    /// them have been listed. Only meaningful when `must_list_uploads` is true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploads_cursor: Option<String>,
+    /// The start of the last completed sweep that listed the replays uploaded by the
+    /// creator. Only replays that were uploaded after this are listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploads_watermark: Option<OffsetDateTime>,
+    /// Replays that were listed during the current sweep, but have not yet been fetched,
+    /// keyed on the replay id. Replays are usually only fetched once all of the groups have
+    /// been listed, so that each replay is fetched once per sweep no matter how many of the
+    /// groups it's in, and its lineages can be taken from every one of them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sweep_replays: BTreeMap<String, SweepReplay>,
+    /// Replays that were emitted during the current sweep before all of the groups had been
+    /// listed, because too many `sweep_replays` had piled up, other than during the first
+    /// sweep. Their lineages only include
+    /// the groups that had been listed by then, so they're fetched again if they're found
+    /// in any other group later in the sweep. Keyed on the replay id, along with its
+    /// `created` time.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub early_replays: BTreeMap<String, OffsetDateTime>,
+    /// Replays that were emitted recently enough that they could be mistaken for new ones,
+    /// along with their `created` time. Only replays uploaded after the start of the last
+    /// completed sweep are kept here, so this stays small.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub recent_replays: BTreeMap<String, OffsetDateTime>,
+    /// Replays that ballchasing had not yet successfully processed when they were fetched,
+    /// keyed on the replay id. These are fetched again at the start of each sweep, and
+    /// emitted once they're `ok`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pending_replays: BTreeMap<String, PendingReplay>,
+    /// The replays that were directly in each group as of the last time that it was listed,
+    /// keyed on the group id. This is used to detect replays that were added to a group
+    /// after they were uploaded, which would otherwise be filtered out by their `created`,
+    /// and to find the lineage of every group that a replay is in when it's emitted. This
+    /// holds the id of every replay in any of the groups, which is around 40 bytes of the
+    /// checkpoint for each of them, so creators with hundreds of thousands of replays have
+    /// checkpoints of several megabytes.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub group_replays: BTreeMap<String, GroupReplays>,
+    /// The ids of the replays that have been emitted and were still in a group as of the
+    /// last completed sweep. This is only tracked for bindings with `trackDeletions`
+    /// enabled, and only includes replays that were emitted while it was.
+    #[serde(default, with = "id_set", skip_serializing_if = "Option::is_none")]
+    pub known_replays: Option<BTreeSet<String>>,
+    /// The groups that have been captured, for bindings that capture groups or their stats.
+    #[serde(default, skip_serializing_if = "CapturedGroups::is_empty")]
+    pub captured_groups: CapturedGroups,
 }
 
 impl BindingState {
-    pub fn new(collection: impl Into<String>, creator_id: impl Into<String>) -> BindingState {
+    pub fn new(
+        collection: impl Into<String>,
+        root: SweepRoot,
+        backfill: u32,
+        resource_config: &ResourceConfig,
+    ) -> BindingState {
         BindingState {
             collection_name: collection.into(),
-            creator_id: creator_id.into(),
+            root,
+            kind: resource_config.kind,
+            backfill: Some(backfill),
+            filters: resource_config.filters.clone(),
             sweep_start: None,
             last_completed_sweep: None,
             todo_groups: VecDeque::new(),
+            groups_cursor: None,
+            must_list_uploads: false,
+            uploads_cursor: None,
+            uploads_watermark: None,
+            sweep_replays: BTreeMap::new(),
+            early_replays: BTreeMap::new(),
+            recent_replays: BTreeMap::new(),
+            pending_replays: BTreeMap::new(),
+            group_replays: BTreeMap::new(),
+            known_replays: resource_config.track_deletions.then(BTreeSet::new),
+            captured_groups: CapturedGroups::default(),
         }
     }
 
-    pub async fn start_sweep(&mut self, fetcher: &Fetcher) -> anyhow::Result<()> {
-        tracing::info!(creator_id = %self.creator_id, "starting sweep");
+    pub async fn start_sweep(
+        &mut self,
+        fetcher: &Fetcher,
+        source: ReplaySource,
+    ) -> anyhow::Result<()> {
+        tracing::info!(root = %self.root, ?source, "starting sweep");
         self.sweep_start = Some(OffsetDateTime::now_utc());
-        let groups = fetcher.fetch_creator_groups(&self.creator_id).await?;
-        tracing::info!(creator_id = %self.creator_id, group_count = %groups.len(), "fetched top-level groups for creator");
+        self.groups_cursor = None;
+        self.must_list_uploads = source.includes_uploads();
+        self.uploads_cursor = None;
+        match &self.root {
+            SweepRoot::Creator(_) if source.includes_groups() => {
+                self.fetch_next_groups(fetcher).await?;
+            }
+            SweepRoot::Creator(_) => {}
+            SweepRoot::Group(group_id) => {
+                let group = fetcher
+                    .fetch_group(group_id)
+                    .await
+                    .context("fetching root group")?;
+                // The group summary doesn't say how many replays or children the group
+                // has, so we always check for both, and always capture it again.
+                let mut groups = vec![TodoGroup {
+                    must_fetch_children: true,
+                    must_fetch_replays: true,
+                    ..TodoGroup::from(group)
+                }];
+                self.captured_groups
+                    .visit(self.kind, self.sweep_start, &mut groups, &[]);
+                self.todo_groups.extend(groups);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches the next page of top-level groups for the creator, which is the first
+    /// page if `groups_cursor` is `None`.
+    pub async fn fetch_next_groups(&mut self, fetcher: &Fetcher) -> anyhow::Result<()> {
+        let SweepRoot::Creator(creator_id) = &self.root else {
+            anyhow::bail!("only creators have top-level groups, not {}", self.root);
+        };
+        let page = fetcher
+            .fetch_creator_groups(creator_id, self.groups_cursor.as_deref())
+            .await
+            .context("listing creator groups")?;
+        tracing::info!(%creator_id, group_count = %page.groups.len(), has_more = page.next.is_some(), "fetched top-level groups for creator");
+        self.groups_cursor = page.next;
+        let mut groups = page.groups;
+        self.captured_groups
+            .visit(self.kind, self.sweep_start, &mut groups, &[]);
         self.todo_groups.extend(groups);
         Ok(())
     }
 
+    /// Marks the sweep as complete, and forgets about any groups that were not seen
+    /// during it, since they've either been deleted or no longer contain any replays.
+    /// Returns the captured groups that were forgotten, and, if `trackDeletions` is enabled,
+    /// the ids of any emitted replays that were in a group as of the previous sweep, but
+    /// are no longer in any group.
+    pub fn complete_sweep(&mut self, resource_config: &ResourceConfig) -> Deletions {
+        let sweep_start = self.sweep_start.take();
+        self.uploads_watermark = sweep_start.filter(|_| resource_config.source.includes_uploads());
+        self.group_replays
+            .retain(|_, g| Some(g.last_seen_sweep) == sweep_start);
+        let (known_groups, deleted_groups) = std::mem::take(&mut self.captured_groups.known)
+            .into_iter()
+            .partition(|(_, g)| Some(g.last_seen_sweep) == sweep_start);
+        self.captured_groups.known = known_groups;
+        self.recent_replays
+            .retain(|_, created| Some(*created) > sweep_start);
+        self.last_completed_sweep = sweep_start;
+
+        let mut deletions = Deletions {
+            replays: Vec::new(),
+            groups: deleted_groups.into_iter().collect(),
+        };
+        if !resource_config.track_deletions {
+            self.known_replays = None;
+            return deletions;
+        }
+        let current: BTreeSet<&String> = self
+            .group_replays
+            .values()
+            .flat_map(|g| g.replay_ids.iter())
+            .collect();
+        let (known, deleted) = self
+            .known_replays
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .partition(|id| current.contains(id));
+        self.known_replays = Some(known);
+        deletions.replays = deleted.into_iter().collect();
+        deletions
+    }
+
+    /// Called once all of the groups have been listed for the current sweep. Forgets about
+    /// any groups that were not seen during it, so they aren't included in the lineages of
+    /// replays, and about the `early_replays`.
+    pub fn finish_listing(&mut self) {
+        let sweep_start = self.sweep_start;
+        self.group_replays
+            .retain(|_, g| Some(g.last_seen_sweep) == sweep_start);
+        self.early_replays.clear();
+    }
+
+    /// Returns the lineage of each group that the replay is directly in. While the groups
+    /// are still being listed, only those that have been listed so far are included, since
+    /// the rest may no longer exist.
+    pub fn replay_lineages(&self, replay_id: &str) -> Vec<Vec<ParentGroup>> {
+        let listing = self.sweep_start.is_some() && !self.is_sweep_complete();
+        self.group_replays
+            .values()
+            .filter(|g| !listing || Some(g.last_seen_sweep) == self.sweep_start)
+            .filter(|g| g.replay_ids.contains(replay_id))
+            .map(|g| g.lineage.clone())
+            .collect()
+    }
+
+    /// Remembers that the replay was emitted, so that a deletion can be emitted once it's
+    /// removed from its groups, so that it isn't mistaken for a new replay by the current or
+    /// next sweep, and so that it's listed again if it was emitted before all of the groups
+    /// had been listed.
+    pub fn record_emitted(&mut self, replay_id: &str, created: OffsetDateTime) {
+        if let Some(known) = self.known_replays.as_mut() {
+            known.insert(replay_id.to_string());
+        }
+        // Every replay is new to the first sweep, so it lists them again without this.
+        if self.last_completed_sweep.is_some()
+            && self.sweep_start.is_some()
+            && !self.is_sweep_complete()
+        {
+            self.early_replays.insert(replay_id.to_string(), created);
+        }
+        let threshold = self.last_completed_sweep.or(self.sweep_start);
+        if threshold.is_none_or(|t| created > t) {
+            self.recent_replays.insert(replay_id.to_string(), created);
+        }
+    }
+
+    /// Returns true once all of the groups have been listed for the current sweep.
     pub fn is_sweep_complete(&self) -> bool {
-        self.todo_groups.is_empty()
+        self.todo_groups.is_empty() && self.groups_cursor.is_none() && !self.must_list_uploads
     }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+/// The previously emitted documents that a completed sweep found to be gone.
+#[derive(Debug)]
+pub struct Deletions {
+    /// The ids of emitted replays that are no longer in any group.
+    pub replays: Vec<String>,
+    /// The captured groups that weren't seen during the sweep, keyed on the group id.
+    pub groups: BTreeMap<String, KnownGroup>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct State {
-    /// The state is keyed on the combination of the creator id and the Flow
-    /// collection name, so that we can easily throw away the state and start
-    /// over if either of those things changes.
+    /// The version of the checkpoint format. Checkpoints from before versioning was
+    /// introduced don't have one, and are treated as version 0.
+    #[serde(default)]
+    pub version: u32,
+    /// The state of each binding, keyed on `binding_key`.
     #[serde(default)]
     pub bindings: BTreeMap<String, BindingState>,
 }
 
+impl Default for State {
+    fn default() -> State {
+        State {
+            version: STATE_VERSION,
+            bindings: BTreeMap::new(),
+        }
+    }
+}
+
+impl State {
+    /// Deserializes a state checkpoint, first upgrading it to the current version if it was
+    /// written by an older version of the connector.
+    pub fn from_checkpoint(state_json: &str) -> anyhow::Result<State> {
+        if state_json.trim().is_empty() {
+            return Ok(State::default());
+        }
+        let mut json: Value =
+            serde_json::from_str(state_json).context("parsing state checkpoint")?;
+        let version = match json.get("version") {
+            Some(v) => v
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .with_context(|| format!("invalid state checkpoint version: {v}"))?,
+            None => 0,
+        };
+        if version > STATE_VERSION {
+            anyhow::bail!(
+                "state checkpoint version {version} is newer than the latest supported version {STATE_VERSION}"
+            );
+        }
+        for (from, migrate) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            tracing::info!(from, to = from + 1, "migrating state checkpoint");
+            migrate(&mut json)
+                .with_context(|| format!("migrating state checkpoint from version {from}"))?;
+        }
+        json["version"] = STATE_VERSION.into();
+        serde_json::from_value(json).context("deserializing state checkpoint")
+    }
+}
+
+/// Version 1 keys each binding on its `binding_key` instead of just the creator id and
+/// collection name, replaces the `creator_id` of each binding with a `root`, since
+/// bindings may now be rooted at a single group instead, and stores the tree of groups
+/// keyed on their ids instead of as arrays.
+fn migrate_v0_to_v1(json: &mut Value) -> anyhow::Result<()> {
+    let Some(bindings) = json.get_mut("bindings") else {
+        return Ok(());
+    };
+    let bindings = bindings
+        .as_object_mut()
+        .context("bindings must be an object")?;
+
+    let mut migrated = serde_json::Map::new();
+    for (old_key, mut binding) in std::mem::take(bindings) {
+        let fields = binding
+            .as_object_mut()
+            .with_context(|| format!("state of binding {old_key} must be an object"))?;
+        let creator_id = fields
+            .remove("creator_id")
+            .with_context(|| format!("state of binding {old_key} is missing creator_id"))?;
+        let collection_name = fields.get("collection_name").and_then(Value::as_str);
+        let (Some(creator), Some(collection_name)) = (creator_id.as_str(), collection_name) else {
+            anyhow::bail!("state of binding {old_key} must have a creator_id and collection_name");
+        };
+        // Migrations must keep producing the same output, so this is deliberately not
+        // written in terms of `binding_key`, which may change in the future.
+        let new_key = format!("creator:{creator};{collection_name}");
+        fields.insert(
+            "root".to_string(),
+            serde_json::json!({ "creator": creator }),
+        );
+        if let Some(todo_groups) = fields.get_mut("todo_groups") {
+            key_groups_by_id(todo_groups)?;
+        }
+        migrated.insert(new_key, binding);
+    }
+    *bindings = migrated;
+    Ok(())
+}
+
+/// Version 2 stores the lineage of each group once, in `group_replays`, instead of storing
+/// the lineages of every group that a replay is in along with each of the `sweep_replays`
+/// and `pending_replays`.
+fn migrate_v1_to_v2(json: &mut Value) -> anyhow::Result<()> {
+    let Some(bindings) = json.get_mut("bindings").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    for (key, binding) in bindings.iter_mut() {
+        let fields = binding
+            .as_object_mut()
+            .with_context(|| format!("state of binding {key} must be an object"))?;
+        let mut group_lineages = BTreeMap::new();
+        for replays_field in ["sweep_replays", "pending_replays"] {
+            let Some(replays) = fields.get_mut(replays_field).and_then(Value::as_object_mut) else {
+                continue;
+            };
+            for replay in replays.values_mut() {
+                let lineages = replay.as_object_mut().and_then(|r| r.remove("lineages"));
+                let Some(Value::Array(lineages)) = lineages else {
+                    continue;
+                };
+                for lineage in lineages {
+                    let group_id = lineage
+                        .as_array()
+                        .and_then(|l| l.last())
+                        .and_then(|g| g.get("id"))
+                        .and_then(Value::as_str)
+                        .map(str::to_string);
+                    if let Some(group_id) = group_id {
+                        group_lineages.insert(group_id, lineage);
+                    }
+                }
+            }
+        }
+        let Some(group_replays) = fields
+            .get_mut("group_replays")
+            .and_then(Value::as_object_mut)
+        else {
+            continue;
+        };
+        for (group_id, lineage) in group_lineages {
+            if let Some(Value::Object(group)) = group_replays.get_mut(&group_id) {
+                group.insert("lineage".to_string(), lineage);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces an array of groups, along with each of their `children`, with an object that's
+/// keyed on the group ids.
+fn key_groups_by_id(groups: &mut Value) -> anyhow::Result<()> {
+    let Value::Array(array) = groups.take() else {
+        anyhow::bail!("groups must be an array");
+    };
+    let mut keyed = serde_json::Map::new();
+    for mut group in array {
+        let id = group
+            .get("id")
+            .and_then(Value::as_str)
+            .context("group must have an id")?
+            .to_string();
+        if let Some(children) = group.get_mut("children") {
+            key_groups_by_id(children)?;
+        }
+        keyed.insert(id, group);
+    }
+    *groups = Value::Object(keyed);
+    Ok(())
+}
+
+/// Serializes groups as an object keyed on their ids, so that the merge patch of a
+/// checkpoint only includes the groups that changed, rather than the whole tree whenever
+/// the front of it changes. Deserialized groups are ordered by id, which is as good as any
+/// other order for the sweep.
+mod groups_by_id {
+    use super::TodoGroup;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::{BTreeMap, VecDeque};
+
+    pub fn serialize<S: Serializer>(groups: &VecDeque<TodoGroup>, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_map(groups.iter().map(|g| (&g.id, g)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<VecDeque<TodoGroup>, D::Error> {
+        let groups = BTreeMap::<String, TodoGroup>::deserialize(d)?;
+        Ok(groups.into_values().collect())
+    }
+}
+
+/// Serializes a set of ids as an object with a `true` value for each of them, so that the
+/// merge patch of a checkpoint only includes the ids that were added or removed.
+mod id_set {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::{BTreeMap, BTreeSet};
+
+    pub fn serialize<S: Serializer>(
+        ids: &Option<BTreeSet<String>>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        match ids {
+            Some(ids) => s.collect_map(ids.iter().map(|id| (id, true))),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Option<BTreeSet<String>>, D::Error> {
+        let ids = Option::<BTreeMap<String, bool>>::deserialize(d)?;
+        Ok(ids.map(|ids| ids.into_keys().collect()))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TodoGroup {
     pub id: String,
     pub name: String,
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created: Option<OffsetDateTime>,
+    /// The number of replays that are directly in this group, according to the listing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_replays: Option<i64>,
+    /// The number of replays that are in the descendants of this group, according to the
+    /// listing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indirect_replays: Option<i64>,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub must_fetch_children: bool,
+    /// The url of the next page of child groups, if some but not all of the children have
+    /// been listed. Only meaningful when `must_fetch_children` is true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub children_cursor: Option<String>,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub must_fetch_replays: bool,
-    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    #[serde(
+        default,
+        with = "groups_by_id",
+        skip_serializing_if = "VecDeque::is_empty"
+    )]
     pub children: VecDeque<TodoGroup>,
 }
 
@@ -68,12 +557,161 @@ impl TodoGroup {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ParentGroup {
     pub id: String,
     pub name: String,
 }
 
+/// The maximum number of consecutive sweeps that a group is skipped because its replay
+/// count is unchanged. A replay could have been removed from the group and another added,
+/// so it's listed again after this many sweeps regardless. Bindings that set
+/// `listUnchangedGroups` never skip groups.
+const MAX_SKIPPED_SWEEPS: u32 = 24;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupReplays {
+    /// The `direct_replays` count of the group when it was last listed. The group is only
+    /// listed again once this changes, or it's been skipped for `MAX_SKIPPED_SWEEPS`.
+    pub direct_replays: i64,
+    /// The ids of the replays that were directly in the group when it was last listed.
+    pub replay_ids: BTreeSet<String>,
+    /// The lineage of the group as of the most recent sweep that saw it, ending with the
+    /// group itself.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lineage: Vec<ParentGroup>,
+    /// The start of the most recent sweep that saw this group.
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_seen_sweep: OffsetDateTime,
+    /// The number of sweeps that have skipped listing the group since it was last listed.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub skipped_sweeps: u32,
+}
+
+impl GroupReplays {
+    /// Returns true if the group doesn't need to be listed again, given its current
+    /// `direct_replays` count.
+    pub fn can_skip(&self, direct_replays: Option<i64>) -> bool {
+        Some(self.direct_replays) == direct_replays && self.skipped_sweeps < MAX_SKIPPED_SWEEPS
+    }
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CapturedGroups {
+    /// Groups that were listed during the current sweep, but whose details have not yet
+    /// been fetched, keyed on the group id.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sweep: BTreeMap<String, SweepGroup>,
+    /// The groups that have been captured, keyed on the group id. A group is only fetched
+    /// again once its listing shows that it has changed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub known: BTreeMap<String, KnownGroup>,
+}
+
+impl CapturedGroups {
+    pub fn is_empty(&self) -> bool {
+        self.sweep.is_empty() && self.known.is_empty()
+    }
+
+    /// Called with each page of groups before they're added to the tree, along with the
+    /// lineage of their parent. Bindings that capture groups or their stats queue any of
+    /// them that are new or have changed into `sweep`, and don't list their replays.
+    pub fn visit(
+        &mut self,
+        kind: BindingKind,
+        sweep_start: Option<OffsetDateTime>,
+        groups: &mut [TodoGroup],
+        parent_lineage: &[ParentGroup],
+    ) {
+        if kind == BindingKind::Replays {
+            return;
+        }
+        let sweep_start = sweep_start.expect("sweep must be in progress");
+        for group in groups.iter_mut() {
+            group.must_fetch_replays = false;
+
+            let previous = self.known.remove(&group.id);
+            let changed = previous.as_ref().is_none_or(|known| {
+                !known.is_same_version(group.direct_replays, group.indirect_replays, &group.name)
+            });
+            let current = KnownGroup {
+                name: group.name.clone(),
+                direct_replays: group.direct_replays,
+                indirect_replays: group.indirect_replays,
+                last_seen_sweep: sweep_start,
+                stats_ids: previous.map(|known| known.stats_ids).unwrap_or_default(),
+            };
+            if changed {
+                self.sweep.insert(
+                    group.id.clone(),
+                    SweepGroup {
+                        parent_groups: parent_lineage.to_vec(),
+                    },
+                );
+            }
+            self.known.insert(group.id.clone(), current);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SweepGroup {
+    /// The lineage of the group's parent, which is empty for top-level groups.
+    pub parent_groups: Vec<ParentGroup>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KnownGroup {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_replays: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indirect_replays: Option<i64>,
+    /// The start of the most recent sweep that saw this group.
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_seen_sweep: OffsetDateTime,
+    /// The ids of the stats documents that were last emitted for the group, keyed on their
+    /// `type`, so that a deletion can be emitted for any that are gone the next time the
+    /// group is fetched. Only used by bindings that capture group stats.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stats_ids: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl KnownGroup {
+    /// Returns true if the listing shows no changes to the group. Groups without any replay
+    /// counts, like the root group of a binding, can't be compared and are always changed.
+    fn is_same_version(
+        &self,
+        direct_replays: Option<i64>,
+        indirect_replays: Option<i64>,
+        name: &str,
+    ) -> bool {
+        (direct_replays.is_some() || indirect_replays.is_some())
+            && self.name == name
+            && self.direct_replays == direct_replays
+            && self.indirect_replays == indirect_replays
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SweepReplay {
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingReplay {
+    /// When the replay was uploaded, which determines when we give up on failed replays.
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: OffsetDateTime,
+    /// The status of the replay when it was last fetched.
+    pub status: ReplayStatus,
+}
+
 impl From<GroupSummary> for TodoGroup {
     fn from(gs: GroupSummary) -> TodoGroup {
         let must_fetch_children = gs.indirect_replays.is_some_and(|n| n > 0);
@@ -81,9 +719,295 @@ impl From<GroupSummary> for TodoGroup {
         TodoGroup {
             id: gs.id,
             name: gs.name,
+            created: gs.created,
+            direct_replays: gs.direct_replays,
+            indirect_replays: gs.indirect_replays,
             must_fetch_children,
+            children_cursor: None,
             must_fetch_replays,
             children: VecDeque::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn migrations_cover_every_version() {
+        assert_eq!(MIGRATIONS.len(), STATE_VERSION as usize);
+    }
+
+    #[test]
+    fn baseline_checkpoint_is_migrated() {
+        let state = State::from_checkpoint(include_str!("testdata/state-v0-baseline.json"))
+            .expect("migrating checkpoint");
+        insta::assert_json_snapshot!(state);
+    }
+
+    #[test]
+    fn v1_checkpoint_is_migrated() {
+        let migrated = State::from_checkpoint(include_str!("testdata/state-v1.json"))
+            .expect("migrating checkpoint");
+        assert_eq!(
+            serde_json::to_value(&migrated).unwrap(),
+            serde_json::from_str::<Value>(include_str!("testdata/state-v2.json")).unwrap()
+        );
+    }
+
+    #[test]
+    fn current_checkpoint_round_trips() {
+        let fixture = include_str!("testdata/state-v2.json");
+        let state = State::from_checkpoint(fixture).expect("deserializing checkpoint");
+        assert_eq!(
+            serde_json::to_value(&state).unwrap(),
+            serde_json::from_str::<Value>(fixture).unwrap()
+        );
+    }
+
+    #[test]
+    fn newer_checkpoints_are_rejected() {
+        let err = State::from_checkpoint(r#"{"version": 99, "bindings": {}}"#).unwrap_err();
+        assert!(err.to_string().contains("newer than"), "{err}");
+    }
+
+    #[test]
+    fn groups_are_listed_again_after_too_many_skipped_sweeps() {
+        let mut known = GroupReplays {
+            direct_replays: 2,
+            replay_ids: BTreeSet::new(),
+            lineage: Vec::new(),
+            last_seen_sweep: OffsetDateTime::UNIX_EPOCH,
+            skipped_sweeps: 0,
+        };
+        assert!(known.can_skip(Some(2)));
+        assert!(!known.can_skip(Some(3)));
+        assert!(!known.can_skip(None));
+
+        known.skipped_sweeps = MAX_SKIPPED_SWEEPS;
+        assert!(!known.can_skip(Some(2)));
+    }
+
+    #[test]
+    fn complete_sweep_prunes_state_and_reports_deleted_replays() {
+        use time::macros::datetime;
+
+        let config: ResourceConfig =
+            serde_json::from_value(serde_json::json!({"creatorId": "123", "trackDeletions": true}))
+                .unwrap();
+        let mut state =
+            BindingState::new("coll", SweepRoot::Creator("123".to_string()), 0, &config);
+        let group = |ids: &[&str], last_seen_sweep| GroupReplays {
+            direct_replays: ids.len() as i64,
+            replay_ids: ids.iter().map(|id| id.to_string()).collect(),
+            lineage: Vec::new(),
+            last_seen_sweep,
+            skipped_sweeps: 0,
+        };
+
+        let first = datetime!(2023-09-01 12:00 UTC);
+        state.sweep_start = Some(first);
+        state.group_replays.insert(
+            "week-1".to_string(),
+            group(&["a", "b", "late", "private"], first),
+        );
+        state
+            .group_replays
+            .insert("week-2".to_string(), group(&["c"], first));
+        // The private replay is listed, but never emitted.
+        for id in ["a", "b", "c"] {
+            state.record_emitted(id, datetime!(2023-09-01 11:00 UTC));
+        }
+        state.record_emitted("late", datetime!(2023-09-01 12:30 UTC));
+        assert!(state.complete_sweep(&config).replays.is_empty());
+        assert_eq!(Some(first), state.last_completed_sweep);
+        assert_eq!(
+            vec!["late"],
+            state.recent_replays.keys().collect::<Vec<_>>()
+        );
+
+        // During the next sweep, two replays are removed from the first group, and the
+        // second group isn't seen at all.
+        let second = datetime!(2023-09-01 13:00 UTC);
+        state.sweep_start = Some(second);
+        state
+            .group_replays
+            .insert("week-1".to_string(), group(&["b", "late"], second));
+        assert_eq!(vec!["a", "c"], state.complete_sweep(&config).replays);
+        assert_eq!(
+            vec!["week-1"],
+            state.group_replays.keys().collect::<Vec<_>>()
+        );
+        assert!(state.recent_replays.is_empty());
+        assert_eq!(
+            Some(&BTreeSet::from(["b".to_string(), "late".to_string()])),
+            state.known_replays.as_ref()
+        );
+    }
+
+    #[test]
+    fn replays_get_the_lineage_of_every_group_they_are_in() {
+        let config: ResourceConfig =
+            serde_json::from_value(serde_json::json!({"creatorId": "123"})).unwrap();
+        let mut state =
+            BindingState::new("coll", SweepRoot::Creator("123".to_string()), 0, &config);
+        let parent = |id: &str| ParentGroup {
+            id: id.to_string(),
+            name: id.to_string(),
+        };
+        let first = OffsetDateTime::UNIX_EPOCH;
+        let second = first + time::Duration::hours(1);
+        let mut group = |id: &str, lineage: Vec<ParentGroup>, last_seen_sweep| {
+            let known = GroupReplays {
+                direct_replays: 1,
+                replay_ids: BTreeSet::from(["replay".to_string()]),
+                lineage,
+                last_seen_sweep,
+                skipped_sweeps: 0,
+            };
+            state.group_replays.insert(id.to_string(), known);
+        };
+        // The replay was captured from the first group, and then added to the second. The
+        // third group was deleted since the last sweep.
+        group("week-1", vec![parent("league"), parent("week-1")], second);
+        group("highlights", vec![parent("highlights")], second);
+        group("deleted", vec![parent("deleted")], first);
+        // While the groups are being listed, only those listed so far are included.
+        state.sweep_start = Some(second);
+        state.must_list_uploads = true;
+        assert_eq!(2, state.replay_lineages("replay").len());
+        state.must_list_uploads = false;
+        state.finish_listing();
+
+        let lineages = state.replay_lineages("replay");
+        let ids: Vec<Vec<&str>> = lineages
+            .iter()
+            .map(|l| l.iter().map(|g| g.id.as_str()).collect())
+            .collect();
+        assert_eq!(ids, vec![vec!["highlights"], vec!["league", "week-1"]]);
+        assert!(state.replay_lineages("other").is_empty());
+    }
+
+    #[test]
+    fn replays_emitted_while_listing_are_remembered_until_listing_finishes() {
+        let config: ResourceConfig =
+            serde_json::from_value(serde_json::json!({"creatorId": "123"})).unwrap();
+        let mut state =
+            BindingState::new("coll", SweepRoot::Creator("123".to_string()), 0, &config);
+        let created = OffsetDateTime::UNIX_EPOCH;
+
+        // Everything is new to the first sweep, so there's no need to remember them.
+        state.sweep_start = Some(created + time::Duration::hours(1));
+        state.must_list_uploads = true;
+        state.record_emitted("first", created);
+        assert!(state.early_replays.is_empty());
+
+        state.last_completed_sweep = state.sweep_start;
+        state.sweep_start = Some(created + time::Duration::hours(2));
+        state.record_emitted("second", created);
+        assert_eq!(
+            vec!["second"],
+            state.early_replays.keys().collect::<Vec<_>>()
+        );
+
+        state.must_list_uploads = false;
+        state.record_emitted("third", created);
+        state.finish_listing();
+        assert!(state.early_replays.is_empty());
+    }
+
+    #[test]
+    fn complete_sweep_reports_groups_that_were_not_seen() {
+        let config: ResourceConfig =
+            serde_json::from_value(serde_json::json!({"creatorId": "123", "kind": "groups"}))
+                .unwrap();
+        let mut state =
+            BindingState::new("coll", SweepRoot::Creator("123".to_string()), 0, &config);
+        let group = |id: &str| TodoGroup {
+            id: id.to_string(),
+            name: id.to_string(),
+            created: None,
+            direct_replays: Some(1),
+            indirect_replays: Some(0),
+            must_fetch_children: false,
+            children_cursor: None,
+            must_fetch_replays: true,
+            children: Default::default(),
+        };
+
+        let first = OffsetDateTime::UNIX_EPOCH;
+        state.sweep_start = Some(first);
+        let mut groups = [group("week-1"), group("week-2")];
+        state
+            .captured_groups
+            .visit(state.kind, state.sweep_start, &mut groups, &[]);
+        assert!(state.complete_sweep(&config).groups.is_empty());
+
+        state.sweep_start = Some(first + time::Duration::hours(1));
+        state
+            .captured_groups
+            .visit(state.kind, state.sweep_start, &mut [group("week-1")], &[]);
+        let deleted = state.complete_sweep(&config);
+        assert_eq!(vec!["week-2"], deleted.groups.keys().collect::<Vec<_>>());
+        assert_eq!(
+            vec!["week-1"],
+            state.captured_groups.known.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn captured_groups_are_queued_when_they_change() {
+        let group = |direct_replays| TodoGroup {
+            id: "league".to_string(),
+            name: "League".to_string(),
+            created: None,
+            direct_replays: Some(direct_replays),
+            indirect_replays: Some(0),
+            must_fetch_children: false,
+            children_cursor: None,
+            must_fetch_replays: true,
+            children: Default::default(),
+        };
+        let mut captured = CapturedGroups::default();
+        let sweep_start = Some(OffsetDateTime::UNIX_EPOCH);
+
+        let mut groups = [group(1)];
+        captured.visit(BindingKind::Groups, sweep_start, &mut groups, &[]);
+        assert!(!groups[0].must_fetch_replays);
+        assert!(captured.sweep.contains_key("league"));
+
+        captured.sweep.clear();
+        captured.visit(BindingKind::Groups, sweep_start, &mut [group(1)], &[]);
+        assert!(captured.sweep.is_empty());
+
+        // The emitted stats are remembered across changes to the group.
+        captured
+            .known
+            .get_mut("league")
+            .unwrap()
+            .stats_ids
+            .insert("team".to_string(), BTreeSet::from(["Blue".to_string()]));
+        captured.visit(BindingKind::Groups, sweep_start, &mut [group(2)], &[]);
+        assert!(captured.sweep.contains_key("league"));
+        assert_eq!(1, captured.known["league"].stats_ids.len());
+
+        // Root groups don't have replay counts, so they're captured again on every sweep.
+        let root = || TodoGroup {
+            direct_replays: None,
+            indirect_replays: None,
+            ..group(0)
+        };
+        captured.sweep.clear();
+        captured.visit(BindingKind::GroupStats, sweep_start, &mut [root()], &[]);
+        captured.sweep.clear();
+        captured.visit(BindingKind::GroupStats, sweep_start, &mut [root()], &[]);
+        assert!(captured.sweep.contains_key("league"));
+
+        let mut groups = [group(1)];
+        let mut other = CapturedGroups::default();
+        other.visit(BindingKind::Replays, sweep_start, &mut groups, &[]);
+        assert!(groups[0].must_fetch_replays);
+        assert!(other.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{read_capture_request, write_capture_response};
+use crate::{read_next_request, write_capture_response};
 use anyhow::Context;
 use proto_flow::{
     capture::{
@@ -8,22 +8,73 @@ use proto_flow::{
     flow::ConnectorState,
 };
 use serde::Serialize;
-use tokio::io;
+use serde_json::Value;
+use tokio::{io, sync::watch};
 
-pub struct Acknowledgements(io::BufReader<io::Stdin>);
+/// The maximum number of checkpoints that may be awaiting acknowledgement from the runtime
+/// before `Emitter::commit` waits for some of them to be acknowledged.
+const MAX_UNACKNOWLEDGED_CHECKPOINTS: u64 = 8;
+
+/// The number of merge patch checkpoints that `Emitter::commit_state` writes between each
+/// full checkpoint of the state.
+const PATCHES_PER_FULL_CHECKPOINT: u32 = 50;
+
+pub struct Acknowledgements(pub io::BufReader<io::Stdin>);
 impl Acknowledgements {
-    pub async fn next_ack(&mut self) -> anyhow::Result<anyhow::Result<()>> {
-        let req = read_capture_request(&mut self.0).await?;
-        if req.acknowledge.is_none() {
+    /// Returns the number of checkpoints acknowledged by the next message, or `None` if
+    /// stdin has been closed.
+    pub async fn next_ack(&mut self) -> anyhow::Result<Option<u64>> {
+        let Some(req) = read_next_request(&mut self.0).await? else {
+            return Ok(None);
+        };
+        let Some(ack) = req.acknowledge else {
             anyhow::bail!("expected Acknowledge message, got: {:?}", req);
         };
-        Ok(Ok(()))
+        Ok(Some(ack.checkpoints.into()))
+    }
+
+    /// Reads acknowledgements until stdin is closed, keeping a running total of the number
+    /// of acknowledged checkpoints in `acked`.
+    pub async fn run(mut self, acked: watch::Sender<u64>) -> anyhow::Result<()> {
+        while let Some(n) = self.next_ack().await? {
+            acked.send_modify(|total| *total += n);
+            tracing::debug!(acknowledged = %n, total = %*acked.borrow(), "read acknowledgement");
+        }
+        tracing::info!("stdin was closed by the runtime");
+        Ok(())
     }
 }
 
-pub struct Emitter(pub io::Stdout);
+pub struct Emitter {
+    stdout: io::Stdout,
+    /// The number of checkpoints that have been committed so far.
+    committed: u64,
+    /// The number of checkpoints that have been acknowledged so far, which is updated by
+    /// `Acknowledgements::run`.
+    acked: watch::Receiver<u64>,
+    /// The state as of the last call to `commit_state`, which subsequent checkpoints are
+    /// diffed against.
+    previous_state: Option<Value>,
+    /// The number of merge patches that have been committed since the last full checkpoint.
+    patches_since_full: u32,
+}
 
 impl Emitter {
+    pub fn new(stdout: io::Stdout, acked: watch::Receiver<u64>) -> Emitter {
+        Emitter {
+            stdout,
+            committed: 0,
+            acked,
+            previous_state: None,
+            patches_since_full: 0,
+        }
+    }
+
+    /// Returns the number of committed checkpoints that have not yet been acknowledged.
+    pub fn outstanding(&self) -> u64 {
+        self.committed.saturating_sub(*self.acked.borrow())
+    }
+
     pub async fn emit_doc(&mut self, binding: u32, doc: &impl Serialize) -> anyhow::Result<()> {
         use tokio::io::AsyncWriteExt;
 
@@ -36,15 +87,53 @@ impl Emitter {
             ..Default::default()
         };
         let resp = serde_json::to_vec(&resp).context("serializing response")?;
-        self.0.write_all(&resp).await.context("writing response")?;
-        self.0
+        self.stdout
+            .write_all(&resp)
+            .await
+            .context("writing response")?;
+        self.stdout
             .write_u8(b'\n')
             .await
             .context("writing response newline")?;
         Ok(())
     }
 
-    pub async fn commit(&mut self, cp: &impl Serialize, merge_patch: bool) -> anyhow::Result<()> {
+    /// Commits a checkpoint of the complete connector state. Only the parts of the state that
+    /// changed since the previous call are written, as a JSON merge patch, except for every
+    /// `PATCHES_PER_FULL_CHECKPOINT`th checkpoint, which is written in full.
+    pub async fn commit_state(&mut self, state: &impl Serialize) -> anyhow::Result<()> {
+        let state = serde_json::to_value(state).context("serializing driver checkpoint")?;
+        match self.previous_state.as_ref() {
+            Some(previous) if self.patches_since_full < PATCHES_PER_FULL_CHECKPOINT => {
+                let patch = merge_patch_diff(previous, &state)
+                    .unwrap_or_else(|| Value::Object(Default::default()));
+                self.commit(&patch, true).await?;
+                self.patches_since_full += 1;
+            }
+            _ => {
+                self.commit(&state, false).await?;
+                self.patches_since_full = 0;
+            }
+        }
+        self.previous_state = Some(state);
+        Ok(())
+    }
+
+    async fn commit(&mut self, cp: &impl Serialize, merge_patch: bool) -> anyhow::Result<()> {
+        if self.outstanding() >= MAX_UNACKNOWLEDGED_CHECKPOINTS {
+            tracing::debug!(outstanding = %self.outstanding(), "waiting for acknowledgements before committing");
+            let committed = self.committed;
+            let wait = self
+                .acked
+                .wait_for(|acked| committed.saturating_sub(*acked) < MAX_UNACKNOWLEDGED_CHECKPOINTS)
+                .await;
+            if wait.is_err() {
+                // Stdin was closed, so there won't be any more acknowledgements. We still
+                // write the checkpoint, since we might be in the middle of shutting down.
+                tracing::debug!("not waiting for acknowledgements because stdin was closed");
+            }
+        }
+
         let updated_json = serde_json::to_string(cp).context("serializing driver checkpoint")?;
         let resp = Response {
             checkpoint: Some(Checkpoint {
@@ -55,8 +144,113 @@ impl Emitter {
             }),
             ..Default::default()
         };
-        write_capture_response(resp, &mut self.0)
+        write_capture_response(resp, &mut self.stdout)
             .await
-            .context("writing checkpoint")
+            .context("writing checkpoint")?;
+        self.committed += 1;
+        Ok(())
+    }
+}
+
+/// Returns a JSON merge patch (RFC 7396) that transforms `before` into `after`, or `None` if
+/// they're equal. Objects are diffed key by key, and everything else is replaced wholesale,
+/// which is why the state stores anything that changes a little at a time as objects.
+/// This relies on `after` not containing any `null` values, since those can't be represented
+/// in a merge patch.
+fn merge_patch_diff(before: &Value, after: &Value) -> Option<Value> {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let mut patch = serde_json::Map::new();
+            for (key, before_value) in before {
+                match after.get(key) {
+                    None => {
+                        patch.insert(key.clone(), Value::Null);
+                    }
+                    Some(after_value) => {
+                        if let Some(diff) = merge_patch_diff(before_value, after_value) {
+                            patch.insert(key.clone(), diff);
+                        }
+                    }
+                }
+            }
+            for (key, after_value) in after {
+                if !before.contains_key(key) {
+                    patch.insert(key.clone(), after_value.clone());
+                }
+            }
+            (!patch.is_empty()).then_some(Value::Object(patch))
+        }
+        _ if before == after => None,
+        _ => Some(after.clone()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn apply_merge_patch(target: &mut Value, patch: &Value) {
+        let Value::Object(patch) = patch else {
+            *target = patch.clone();
+            return;
+        };
+        if !target.is_object() {
+            *target = Value::Object(Default::default());
+        }
+        let target = target.as_object_mut().unwrap();
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                apply_merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_patches_only_contain_changes() {
+        let before = json!({
+            "bindings": {
+                "a;replays": {
+                    "todo_groups": [{"id": "g1"}, {"id": "g2"}],
+                    "group_replays": {"g0": {"direct_replays": 2}},
+                    "sweep_replays": {"r1": {"created": "2023-01-01T00:00:00Z"}},
+                },
+                "b;replays": {"todo_groups": [{"id": "g3"}]},
+            }
+        });
+        let after = json!({
+            "bindings": {
+                "a;replays": {
+                    "todo_groups": [{"id": "g2"}],
+                    "group_replays": {
+                        "g0": {"direct_replays": 2},
+                        "g1": {"direct_replays": 1},
+                    },
+                },
+                "b;replays": {"todo_groups": [{"id": "g3"}]},
+            }
+        });
+
+        let patch = merge_patch_diff(&before, &after).unwrap();
+        assert_eq!(
+            patch,
+            json!({
+                "bindings": {
+                    "a;replays": {
+                        "todo_groups": [{"id": "g2"}],
+                        "group_replays": {"g1": {"direct_replays": 1}},
+                        "sweep_replays": null,
+                    },
+                }
+            })
+        );
+
+        let mut patched = before.clone();
+        apply_merge_patch(&mut patched, &patch);
+        assert_eq!(patched, after);
+
+        assert_eq!(merge_patch_diff(&after, &after), None);
     }
 }